@@ -0,0 +1,63 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! HTTP middleware compressing the requests to InfluxDB and its responses with gzip.
+
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use surf::middleware::{Middleware, Next};
+use surf::{Client, Request, Response};
+
+const GZIP: &str = "gzip";
+
+/// Compress the line-protocol body of write requests (`Content-Encoding: gzip`),
+/// and request compressed responses to queries (`Accept-Encoding: gzip`).
+pub(crate) struct Gzip;
+
+#[async_trait]
+impl Middleware for Gzip {
+    async fn handle(
+        &self,
+        mut req: Request,
+        client: Client,
+        next: Next<'_>,
+    ) -> surf::Result<Response> {
+        if req.url().path().ends_with("/write") {
+            let body = req.take_body().into_bytes().await?;
+            let mut encoder =
+                GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::default());
+            encoder.write_all(&body)?;
+            req.set_body(encoder.finish()?);
+            req.insert_header("Content-Encoding", GZIP);
+        }
+        req.insert_header("Accept-Encoding", GZIP);
+
+        let mut res = next.run(req, client).await?;
+        let compressed = res
+            .header("Content-Encoding")
+            .map(|v| v.last().as_str().eq_ignore_ascii_case(GZIP))
+            .unwrap_or(false);
+        if compressed {
+            let body = res.take_body().into_bytes().await?;
+            let mut decoded = Vec::with_capacity(body.len() * 4);
+            GzDecoder::new(&body[..]).read_to_end(&mut decoded)?;
+            res.remove_header("Content-Encoding");
+            res.set_body(decoded);
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,265 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Failover between several InfluxDB endpoints (e.g. an InfluxDB relay pair).
+
+use crate::retry;
+use influxdb::Client;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FailoverPolicy {
+    // all requests go to the active endpoint (the 1st one, until it fails)
+    #[default]
+    Primary,
+    // writes go to the active endpoint, reads are spread over all the endpoints
+    RoundRobin,
+}
+
+/// The InfluxDB endpoints of a volume, and which one is currently active.
+pub(crate) struct Endpoints {
+    urls: Vec<String>,
+    policy: FailoverPolicy,
+    active: AtomicUsize,
+    next_read: AtomicUsize,
+    failovers: AtomicU64,
+    last_failover: Mutex<Option<(String, SystemTime)>>,
+}
+
+impl Endpoints {
+    pub(crate) fn new(urls: Vec<String>, policy: FailoverPolicy) -> Self {
+        Endpoints {
+            urls,
+            policy,
+            active: AtomicUsize::new(0),
+            next_read: AtomicUsize::new(0),
+            failovers: AtomicU64::new(0),
+            last_failover: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn urls(&self) -> &[String] {
+        &self.urls
+    }
+
+    pub(crate) fn policy(&self) -> FailoverPolicy {
+        self.policy
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    // The endpoint to be used for a request
+    fn pick(&self, read: bool) -> usize {
+        if read && self.policy == FailoverPolicy::RoundRobin {
+            self.next_read.fetch_add(1, Ordering::Relaxed) % self.urls.len()
+        } else {
+            self.active()
+        }
+    }
+
+    /// Switch to another endpoint (if `failed` is still the active one).
+    pub(crate) fn on_failure<E: std::fmt::Display>(&self, failed: usize, e: &E) {
+        let next = (failed + 1) % self.urls.len();
+        if next != failed && self.switch(failed, next) {
+            let event = format!(
+                "switched from {} to {} after error: {}",
+                self.urls[failed], self.urls[next], e
+            );
+            warn!("InfluxDB endpoint failover: {}", event);
+            self.record_failover(event);
+        }
+    }
+
+    /// Switch back to the primary endpoint (e.g. once it's reachable again).
+    pub(crate) fn fail_back(&self) {
+        let active = self.active();
+        if active != 0 && self.switch(active, 0) {
+            let event = format!(
+                "switched back from {} to {}",
+                self.urls[active], self.urls[0]
+            );
+            warn!("InfluxDB endpoint failover: {}", event);
+            self.record_failover(event);
+        }
+    }
+
+    fn switch(&self, from: usize, to: usize) -> bool {
+        self.active
+            .compare_exchange(from, to, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn record_failover(&self, event: String) {
+        self.failovers.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut last) = self.last_failover.lock() {
+            *last = Some((event, SystemTime::now()));
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let last_failover = match self.last_failover.lock() {
+            Ok(guard) => match &*guard {
+                Some((event, time)) => json!({
+                    "event": event,
+                    "time": humantime::format_rfc3339(*time).to_string(),
+                }),
+                None => serde_json::Value::Null,
+            },
+            Err(_) => serde_json::Value::Null,
+        };
+        json!({
+            "urls": self.urls,
+            "policy": self.policy,
+            "active": self.urls[self.active()],
+            "failovers": self.failovers.load(Ordering::Relaxed),
+            "last_failover": last_failover,
+        })
+    }
+}
+
+/// InfluxDB clients on a same database (and with same credentials), one per endpoint.
+#[derive(Clone)]
+pub(crate) struct ClientPool {
+    clients: Vec<Client>,
+    endpoints: Arc<Endpoints>,
+}
+
+impl ClientPool {
+    pub(crate) fn new(
+        endpoints: &Arc<Endpoints>,
+        db: &str,
+        http_client: &surf::Client,
+        credentials: Option<(&str, &str)>,
+    ) -> Self {
+        let clients = endpoints
+            .urls()
+            .iter()
+            .map(|url| {
+                let client = Client::new(url, db).with_http_client(http_client.clone());
+                match credentials {
+                    Some((username, password)) => client.with_auth(username, password),
+                    None => client,
+                }
+            })
+            .collect();
+        ClientPool {
+            clients,
+            endpoints: endpoints.clone(),
+        }
+    }
+
+    pub(crate) fn endpoints(&self) -> &Arc<Endpoints> {
+        &self.endpoints
+    }
+
+    pub(crate) fn client(&self, endpoint: usize) -> &Client {
+        &self.clients[endpoint]
+    }
+
+    /// The client on the active endpoint.
+    pub(crate) fn active(&self) -> &Client {
+        &self.clients[self.endpoints.active()]
+    }
+
+    pub(crate) fn database_name(&self) -> &str {
+        self.active().database_name()
+    }
+
+    /// Run a request, on the next endpoint(s) if it fails with a transient error.
+    pub(crate) async fn run<'a, T, F, Fut>(
+        &'a self,
+        read: bool,
+        mut request: F,
+    ) -> Result<T, influxdb::Error>
+    where
+        F: FnMut(&'a Client) -> Fut,
+        Fut: Future<Output = Result<T, influxdb::Error>>,
+    {
+        let mut attempts = self.clients.len();
+        loop {
+            let i = self.endpoints.pick(read);
+            match request(&self.clients[i]).await {
+                Err(e) if attempts > 1 && retry::is_transient(&e) => {
+                    self.endpoints.on_failure(i, &e);
+                    attempts -= 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints(policy: FailoverPolicy) -> Endpoints {
+        Endpoints::new(
+            vec!["http://a".into(), "http://b".into(), "http://c".into()],
+            policy,
+        )
+    }
+
+    #[test]
+    fn primary_failover() {
+        let endpoints = endpoints(FailoverPolicy::Primary);
+        assert_eq!(endpoints.pick(true), 0);
+        endpoints.on_failure(0, &"down");
+        assert_eq!(endpoints.active(), 1);
+        assert_eq!(endpoints.pick(true), 1);
+        assert_eq!(endpoints.pick(false), 1);
+        // a late failure of a former active endpoint doesn't switch again
+        endpoints.on_failure(0, &"down");
+        assert_eq!(endpoints.active(), 1);
+        endpoints.on_failure(1, &"down");
+        endpoints.on_failure(2, &"down");
+        assert_eq!(endpoints.active(), 0);
+        endpoints.on_failure(0, &"down");
+        endpoints.fail_back();
+        assert_eq!(endpoints.active(), 0);
+        assert_eq!(endpoints.to_json()["failovers"], 5);
+        assert_eq!(
+            endpoints.to_json()["last_failover"]["event"],
+            "switched back from http://b to http://a"
+        );
+    }
+
+    #[test]
+    fn round_robin_reads() {
+        let endpoints = endpoints(FailoverPolicy::RoundRobin);
+        let reads: Vec<usize> = (0..4).map(|_| endpoints.pick(true)).collect();
+        assert_eq!(reads, [0, 1, 2, 0]);
+        // the writes go to the active endpoint
+        assert_eq!(endpoints.pick(false), 0);
+        endpoints.on_failure(0, &"down");
+        assert_eq!(endpoints.pick(false), 1);
+    }
+
+    #[test]
+    fn single_endpoint() {
+        let endpoints = Endpoints::new(vec!["http://a".into()], FailoverPolicy::Primary);
+        endpoints.on_failure(0, &"down");
+        assert_eq!(endpoints.active(), 0);
+        assert_eq!(endpoints.to_json()["failovers"], 0);
+    }
+}
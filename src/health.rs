@@ -0,0 +1,240 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Monitoring of the connection to InfluxDB, probing its `/ping` endpoint in background.
+
+use crate::endpoints::{ClientPool, FailoverPolicy};
+use crate::stats::LastError;
+use async_std::task;
+use influxdb::Client;
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+// number of consecutive failed probes after which InfluxDB is considered down
+const DOWN_THRESHOLD: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HealthState {
+    // the last probe succeeded
+    Connected,
+    // not probed yet, or the last probes failed (but less than DOWN_THRESHOLD)
+    Degraded,
+    // the last DOWN_THRESHOLD probes failed
+    Down,
+}
+
+impl HealthState {
+    fn from_u8(v: u8) -> HealthState {
+        match v {
+            0 => HealthState::Connected,
+            1 => HealthState::Degraded,
+            _ => HealthState::Down,
+        }
+    }
+}
+
+/// The health of the connection to InfluxDB.
+pub(crate) struct Health {
+    state: AtomicU8,
+    consecutive_failures: AtomicU32,
+    // the InfluxDB version, as returned by last successful probe
+    version: Mutex<Option<String>>,
+    last_error: LastError,
+    // the tasks to run once InfluxDB is reachable (see `when_connected`)
+    pending: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+}
+
+impl Health {
+    fn new() -> Self {
+        Health {
+            state: AtomicU8::new(HealthState::Degraded as u8),
+            consecutive_failures: AtomicU32::new(0),
+            version: Mutex::new(None),
+            last_error: LastError::default(),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn state(&self) -> HealthState {
+        HealthState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.state() == HealthState::Connected
+    }
+
+    /// Run a task once InfluxDB is reachable: now if it is, or by the probe once it is.
+    pub(crate) fn when_connected<F: FnOnce() + Send + 'static>(&self, f: F) {
+        // Note: the state is checked under the lock, thus the probe runs the task if it connects meanwhile
+        if let Ok(mut pending) = self.pending.lock() {
+            if !self.is_connected() {
+                pending.push(Box::new(f));
+                return;
+            }
+        }
+        f()
+    }
+
+    fn run_pending(&self) {
+        let pending = match self.pending.lock() {
+            Ok(mut pending) => std::mem::take(&mut *pending),
+            Err(_) => return,
+        };
+        for f in pending {
+            f()
+        }
+    }
+
+    fn set_state(&self, state: HealthState) {
+        let previous = HealthState::from_u8(self.state.swap(state as u8, Ordering::Relaxed));
+        if previous != state {
+            if state == HealthState::Connected {
+                info!("Connection to InfluxDB is now {:?}", state);
+            } else {
+                warn!(
+                    "Connection to InfluxDB changed from {:?} to {:?}",
+                    previous, state
+                );
+            }
+        }
+    }
+
+    fn on_success(&self, version: String) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if let Ok(mut v) = self.version.lock() {
+            *v = Some(version);
+        }
+        self.set_state(HealthState::Connected);
+    }
+
+    fn on_failure<E: std::fmt::Display>(&self, e: &E) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        self.last_error.set(e);
+        if failures >= DOWN_THRESHOLD {
+            self.set_state(HealthState::Down);
+        } else {
+            self.set_state(HealthState::Degraded);
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let version = self.version.lock().ok().and_then(|v| v.clone());
+        json!({
+            "state": self.state(),
+            "consecutive_failures": self.consecutive_failures.load(Ordering::Relaxed),
+            "influxdb_version": version,
+            "last_error": self.last_error.to_json(),
+        })
+    }
+}
+
+/// Start probing InfluxDB periodically, until the returned Health is dropped.
+/// `on_first_connection` is called once, the first time InfluxDB is reachable.
+/// If the active endpoint is not reachable, switch to the next one.
+pub(crate) fn start_probe<F>(
+    clients: ClientPool,
+    period: Duration,
+    timeout: Duration,
+    on_first_connection: F,
+) -> Arc<Health>
+where
+    F: FnOnce(&Client) + Send + 'static,
+{
+    let health = Arc::new(Health::new());
+    let weak: Weak<Health> = Arc::downgrade(&health);
+    task::spawn(async move {
+        let mut on_first_connection = Some(on_first_connection);
+        loop {
+            let endpoints = clients.endpoints();
+            let active = endpoints.active();
+            let client = clients.client(active);
+            let result = async_std::future::timeout(timeout, client.ping()).await;
+            let health = match weak.upgrade() {
+                Some(health) => health,
+                // the volume has been dropped
+                None => break,
+            };
+            match result {
+                Ok(Ok((build, version))) => {
+                    debug!("InfluxDB ping: {} {}", build, version);
+                    health.on_success(version);
+                    health.run_pending();
+                    if let Some(f) = on_first_connection.take() {
+                        f(client);
+                    }
+                    // switch back to the primary endpoint once it's reachable again
+                    if active != 0 && endpoints.policy() == FailoverPolicy::Primary {
+                        let primary = clients.client(0);
+                        if let Ok(Ok(_)) = async_std::future::timeout(timeout, primary.ping()).await
+                        {
+                            endpoints.fail_back();
+                        }
+                    }
+                }
+                Ok(Err(e)) => {
+                    debug!(
+                        "Failed to ping InfluxDB at {} : {}",
+                        client.database_url(),
+                        e
+                    );
+                    health.on_failure(&e);
+                    endpoints.on_failure(active, &e);
+                }
+                Err(_) => {
+                    debug!(
+                        "Failed to ping InfluxDB at {} : no response after {:?}",
+                        client.database_url(),
+                        timeout
+                    );
+                    let e = format!("no response after {timeout:?}");
+                    health.on_failure(&e);
+                    endpoints.on_failure(active, &e);
+                }
+            }
+            drop(health);
+            task::sleep(period).await;
+        }
+    });
+    health
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn when_connected() {
+        let health = Health::new();
+        let ran = Arc::new(AtomicBool::new(false));
+        let r = ran.clone();
+        health.when_connected(move || r.store(true, Ordering::Relaxed));
+        assert!(!ran.load(Ordering::Relaxed));
+        health.on_failure(&"refused");
+        assert!(!ran.load(Ordering::Relaxed));
+        health.on_success("1.8".into());
+        health.run_pending();
+        assert!(ran.load(Ordering::Relaxed));
+
+        // run at once once connected
+        let r = ran.clone();
+        health.when_connected(move || r.store(false, Ordering::Relaxed));
+        assert!(!ran.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,563 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Construction of the InfluxQL statements used by the backend.
+//!
+//! Measurement names come from zenoh keys and database/user names from the configuration,
+//! thus they must never be interpolated as-is in a statement.
+//! All the statements are built here, with identifiers, string literals and regexes escaped
+//! according to https://docs.influxdata.com/influxdb/v1.8/query_language/spec/#literals
+
+use std::fmt;
+use zenoh::prelude::keyexpr;
+
+/// A double-quoted InfluxQL identifier (measurement, database, user or field name).
+pub(crate) struct Ident<'a>(pub(crate) &'a str);
+
+impl fmt::Display for Ident<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quoted(f, self.0, '"')
+    }
+}
+
+/// A single-quoted InfluxQL string literal.
+pub(crate) struct Literal<'a>(pub(crate) &'a str);
+
+impl fmt::Display for Literal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_quoted(f, self.0, '\'')
+    }
+}
+
+// InfluxQL only supports the \\, \", \' and \n escape sequences within quoted strings,
+// and an unescaped newline ends the statement.
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str, quote: char) -> fmt::Result {
+    use fmt::Write;
+    f.write_char(quote)?;
+    for c in s.chars() {
+        match c {
+            '\\' => f.write_str(r"\\")?,
+            '\n' => f.write_str(r"\n")?,
+            c if c == quote => {
+                f.write_char('\\')?;
+                f.write_char(c)?;
+            }
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char(quote)
+}
+
+// Escape a literal string to be part of an InfluxQL regex (i.e. between '/').
+fn push_regex_escaped(result: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '/' => result.push_str(r"\/"),
+            '\n' => result.push_str(r"\n"),
+            '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c => result.push(c),
+        }
+    }
+}
+
+// Returns an InfluxDB regex (see https://docs.influxdata.com/influxdb/v1.8/query_language/explore-data/#regular-expressions)
+// corresponding to the list of path expressions, each prefixed with a literal prefix (possibly empty). I.e.:
+// Replace "**" with "[^#]*", "*" and "$*" with "[^\/#]*"  and "/" with "\/", escaping any other regex meta-character.
+// As '#' is forbidden in key expressions, the wildcards never match it: without namespace (empty prefix),
+// the measurements of the namespaced keys, of the quarantined samples and the tombstones are excluded.
+// Surround each with '^' and '$', concat them with "|", and surround the result with '/'.
+pub(crate) fn key_exprs_to_regex(prefix: &str, path_exprs: &[&keyexpr]) -> String {
+    let mut result = String::with_capacity(2 * path_exprs[0].len());
+    result.push('/');
+    for (i, path_expr) in path_exprs.iter().enumerate() {
+        if i != 0 {
+            result.push('|');
+        }
+        result.push('^');
+        push_regex_escaped(&mut result, prefix);
+        let mut chunk = String::new();
+        let mut chars = path_expr.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => {
+                    // "$*": the sub-chunk wildcard, as "*" within a chunk
+                    let sub_chunk = chunk.ends_with('$');
+                    if sub_chunk {
+                        chunk.pop();
+                    }
+                    push_regex_escaped(&mut result, &chunk);
+                    chunk.clear();
+                    if sub_chunk {
+                        result.push_str(r"[^\/#]*");
+                    } else if chars.peek() == Some(&'*') {
+                        result.push_str("[^#]*");
+                        chars.next();
+                    } else {
+                        result.push_str(r"[^\/#]*");
+                    }
+                }
+                _ => chunk.push(c),
+            }
+        }
+        push_regex_escaped(&mut result, &chunk);
+        result.push('$');
+    }
+    result.push('/');
+    result
+}
+
+pub(crate) fn show_databases() -> String {
+    "SHOW DATABASES".into()
+}
+
+pub(crate) fn create_database(db: &str) -> String {
+    format!("CREATE DATABASE {}", Ident(db))
+}
+
+pub(crate) fn drop_database(db: &str) -> String {
+    format!("DROP DATABASE {}", Ident(db))
+}
+
+// The owners marked in a database (other than the client's one)
+pub(crate) fn select_owners(db: &str, measurement: &str) -> String {
+    format!(
+        "SELECT \"owner\" FROM {}..{}",
+        Ident(db),
+        Ident(measurement)
+    )
+}
+
+pub(crate) fn grant(privilege: &str, db: &str, username: &str) -> String {
+    format!("GRANT {privilege} ON {} TO {}", Ident(db), Ident(username))
+}
+
+pub(crate) fn show_grants(username: &str) -> String {
+    format!("SHOW GRANTS FOR {}", Ident(username))
+}
+
+// The regex matching the measurements of the keys in a namespace (possibly empty):
+// the namespace's prefix followed by a name without '#', as forbidden in key expressions.
+// Thus without namespace, the measurements of the namespaced keys, of the quarantined samples and the tombstones are excluded.
+fn keys_regex(prefix: &str) -> String {
+    let mut regex = String::from("/^");
+    push_regex_escaped(&mut regex, prefix);
+    regex.push_str("[^#]*$/");
+    regex
+}
+
+// Drop the series of all the measurements of the keys in a namespace (possibly empty),
+// of their quarantined samples (tagged with their measurement) and of their tombstones (see select_tombstones())
+pub(crate) fn drop_all_series(prefix: &str, tombstones: &str, quarantine: &str) -> String {
+    format!(
+        "DROP SERIES FROM {}; DROP SERIES FROM {} WHERE {} =~ {}; DROP SERIES FROM {} WHERE {} =~ {}",
+        keys_regex(prefix),
+        Ident(quarantine),
+        Ident("key"),
+        keys_regex(prefix),
+        Ident(tombstones),
+        Ident("measurement"),
+        keys_regex(prefix)
+    )
+}
+
+pub(crate) fn drop_measurement(measurement: &str) -> String {
+    format!("DROP MEASUREMENT {}", Ident(measurement))
+}
+
+// The timestamp of the latest deletion of a measurement
+pub(crate) fn select_deletion_timestamp(measurement: &str) -> String {
+    format!(
+        "SELECT {} FROM {} WHERE kind={} ORDER BY time DESC LIMIT 1",
+        Ident("timestamp"),
+        Ident(measurement),
+        Literal("DEL")
+    )
+}
+
+// A point that is not a deletion marker in a measurement, if any
+pub(crate) fn select_one_put(measurement: &str) -> String {
+    format!(
+        "SELECT {} FROM {} WHERE kind!={} LIMIT 1",
+        Ident("kind"),
+        Ident(measurement),
+        Literal("DEL")
+    )
+}
+
+// A point that is not a deletion marker in a measurement, more recent than a time, if any
+pub(crate) fn select_put_after(measurement: &str, influx_time: u128) -> String {
+    format!(
+        "SELECT {} FROM {} WHERE kind!={} AND time > {influx_time} LIMIT 1",
+        Ident("timestamp"),
+        Ident(measurement),
+        Literal("DEL")
+    )
+}
+
+// The latest point of a measurement (possibly a deletion marker)
+pub(crate) fn select_last_point(measurement: &str) -> String {
+    format!(
+        "SELECT {}, {}, {}, {}, {}, {} FROM {} ORDER BY time DESC LIMIT 1",
+        Ident("kind"),
+        Ident("timestamp"),
+        Ident("encoding_prefix"),
+        Ident("encoding_suffix"),
+        Ident("base64"),
+        Ident("value"),
+        Ident(measurement)
+    )
+}
+
+// The zenoh timestamp (and time) of the points (possibly from a source) within a time range [from, to) of a measurement
+pub(crate) fn select_puts_between(measurement: &str, from: u128, to: u128, source: &str) -> String {
+    format!(
+        "SELECT {} FROM {} WHERE kind={} AND {} AND time >= {from} AND time < {to}",
+        Ident("timestamp"),
+        Ident(measurement),
+        Literal("PUT"),
+        source_condition(source)
+    )
+}
+
+// The condition on the source of the points (the ID of their zenoh timestamp)
+pub(crate) fn source_condition(source: &str) -> String {
+    format!("{}={}", Ident("source"), Literal(source))
+}
+
+pub(crate) fn delete_before(measurement: &str, influx_time: u128) -> String {
+    format!(
+        "DELETE FROM {} WHERE time < {influx_time}",
+        Ident(measurement)
+    )
+}
+
+// The number of points of a measurement (including the deletion markers)
+pub(crate) fn count_points(measurement: &str) -> String {
+    format!(
+        "SELECT COUNT({}) FROM {}",
+        Ident("timestamp"),
+        Ident(measurement)
+    )
+}
+
+// The exact number of measurements of the keys in a namespace (possibly empty)
+pub(crate) fn show_measurement_cardinality(prefix: &str) -> String {
+    format!(
+        "SHOW MEASUREMENT EXACT CARDINALITY FROM {}",
+        keys_regex(prefix)
+    )
+}
+
+// The number of keys in a namespace (possibly empty) with quarantined samples (tagged with their measurement)
+pub(crate) fn show_quarantined_keys_cardinality(quarantine: &str, prefix: &str) -> String {
+    format!(
+        "SHOW TAG VALUES EXACT CARDINALITY FROM {} WITH KEY = {} WHERE {} =~ {}",
+        Ident(quarantine),
+        Ident("key"),
+        Ident("key"),
+        keys_regex(prefix)
+    )
+}
+
+// A page of the measurements of the keys in a namespace (possibly empty), sorted by name,
+// and following a measurement of the namespace if any (see following_names_regex()).
+pub(crate) fn show_measurements(
+    prefix: &str,
+    limit: usize,
+    offset: usize,
+    after: Option<&str>,
+) -> String {
+    format!(
+        "SHOW MEASUREMENTS WITH MEASUREMENT =~ {}{}",
+        following_names_regex(prefix, after),
+        limit_clause(limit, offset)
+    )
+}
+
+// A page of the dropped measurements of the keys in a namespace (possibly empty), as tagged in the tombstones measurement,
+// sorted by name, and following a measurement of the namespace if any (see following_names_regex()).
+pub(crate) fn show_tombstoned_measurements(
+    tombstones: &str,
+    prefix: &str,
+    limit: usize,
+    offset: usize,
+    after: Option<&str>,
+) -> String {
+    format!(
+        "SHOW TAG VALUES FROM {} WITH KEY = {} WHERE {} =~ {}{}",
+        Ident(tombstones),
+        Ident("measurement"),
+        Ident("measurement"),
+        following_names_regex(prefix, after),
+        limit_clause(limit, offset)
+    )
+}
+
+fn limit_clause(limit: usize, offset: usize) -> String {
+    if offset == 0 {
+        format!(" LIMIT {limit}")
+    } else {
+        format!(" LIMIT {limit} OFFSET {offset}")
+    }
+}
+
+// The regex matching the names of the keys in a namespace (possibly empty), following a name of the namespace if any.
+// Note: the names following a long name may include some preceding it (see push_following_regex()),
+// sorted before the others, to be skipped by the caller.
+fn following_names_regex(prefix: &str, after: Option<&str>) -> String {
+    let mut regex = String::from("/^");
+    push_regex_escaped(&mut regex, prefix);
+    match after.map(|name| name.strip_prefix(prefix).unwrap_or(name)) {
+        Some(after) => push_following_regex(&mut regex, after),
+        None => regex.push_str("[^#]*"),
+    }
+    regex.push_str("$/");
+    regex
+}
+
+// max number of characters of a name compared by push_following_regex() (bounding the regex nesting)
+const MAX_FOLLOWING_CHARS: usize = 128;
+
+// Push a regex matching the key names (without '#') following `s` in the order of their characters
+// (as InfluxDB sorts the names): at the 1st differing character, either a greater one, or any character after the end of `s`.
+// Only the first MAX_FOLLOWING_CHARS characters are compared: beyond, the names starting with them all match.
+fn push_following_regex(result: &mut String, s: &str) {
+    let mut groups = 0;
+    for c in s.chars().take(MAX_FOLLOWING_CHARS) {
+        result.push_str("(?:");
+        if c != char::MAX {
+            push_greater_class(result, c);
+            result.push_str("[^#]*|");
+        }
+        push_regex_escaped(result, c.encode_utf8(&mut [0; 4]));
+        groups += 1;
+    }
+    result.push_str(if s.chars().count() > MAX_FOLLOWING_CHARS {
+        "[^#]*"
+    } else {
+        "[^#]+"
+    });
+    for _ in 0..groups {
+        result.push(')');
+    }
+}
+
+// Push a regex class matching the characters greater than `c`, but '#'
+fn push_greater_class(result: &mut String, c: char) {
+    use fmt::Write;
+    let from = c as u32 + 1;
+    result.push('[');
+    if from < '#' as u32 {
+        let _ = write!(result, r"\x{{{from:x}}}-\x{{22}}");
+    }
+    let _ = write!(
+        result,
+        r"\x{{{:x}}}-\x{{10ffff}}]",
+        from.max('#' as u32 + 1)
+    );
+}
+
+// The regex matching exactly the measurements of a list
+fn measurements_regex(measurements: &[String]) -> String {
+    let mut regex = String::from("/^(?:");
+    for (i, measurement) in measurements.iter().enumerate() {
+        if i != 0 {
+            regex.push('|');
+        }
+        push_regex_escaped(&mut regex, measurement);
+    }
+    regex.push_str(")$/");
+    regex
+}
+
+// The latest timestamp (of a point or of a deletion marker) of each measurement of a list
+pub(crate) fn select_last_timestamps(measurements: &[String]) -> String {
+    format!(
+        "SELECT LAST({}) FROM {}",
+        Ident("timestamp"),
+        measurements_regex(measurements)
+    )
+}
+
+// The latest point (possibly a deletion marker) of each measurement of a list
+pub(crate) fn select_last_points(measurements: &[String]) -> String {
+    format!(
+        "SELECT {}, {}, {}, {}, {}, {} FROM {} ORDER BY time DESC LIMIT 1",
+        Ident("kind"),
+        Ident("timestamp"),
+        Ident("encoding_prefix"),
+        Ident("encoding_suffix"),
+        Ident("base64"),
+        Ident("value"),
+        measurements_regex(measurements)
+    )
+}
+
+// The latest deletion timestamp of each dropped measurement of a list (tagged in the tombstones measurement)
+pub(crate) fn select_tombstones(tombstones: &str, measurements: &[String]) -> String {
+    format!(
+        "SELECT LAST({}) FROM {} WHERE {} =~ {} GROUP BY {}",
+        Ident("timestamp"),
+        Ident(tombstones),
+        Ident("measurement"),
+        measurements_regex(measurements),
+        Ident("measurement")
+    )
+}
+
+// Select all the points from the measurements matching an InfluxDB regex (see key_exprs_to_regex())
+pub(crate) fn select_all(regex: &str, clauses: &str) -> String {
+    if clauses.is_empty() {
+        format!("SELECT * FROM {regex}")
+    } else {
+        format!("SELECT * FROM {regex} {clauses}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex(prefix: &str, exprs: &[&str]) -> String {
+        let exprs: Vec<&keyexpr> = exprs.iter().map(|e| keyexpr::new(*e).unwrap()).collect();
+        key_exprs_to_regex(prefix, &exprs)
+    }
+
+    #[test]
+    fn ident_escaping() {
+        assert_eq!(Ident("a").to_string(), r#""a""#);
+        assert_eq!(Ident(r#"a"b"#).to_string(), r#""a\"b""#);
+        assert_eq!(Ident("a'b").to_string(), r#""a'b""#);
+        assert_eq!(Ident(r"a\b").to_string(), r#""a\\b""#);
+        assert_eq!(Ident("a\nb").to_string(), r#""a\nb""#);
+        assert_eq!(Ident("a/b").to_string(), r#""a/b""#);
+    }
+
+    #[test]
+    fn literal_escaping() {
+        assert_eq!(Literal("DEL").to_string(), "'DEL'");
+        assert_eq!(Literal("it's").to_string(), r"'it\'s'");
+        assert_eq!(Literal(r#"a"b"#).to_string(), r#"'a"b'"#);
+        assert_eq!(Literal(r"a\b").to_string(), r"'a\\b'");
+        assert_eq!(Literal("a\nb").to_string(), r"'a\nb'");
+    }
+
+    #[test]
+    fn statements_escaping() {
+        assert_eq!(
+            drop_database(r#"db"; DROP DATABASE "x"#),
+            r#"DROP DATABASE "db\"; DROP DATABASE \"x""#
+        );
+        assert_eq!(
+            grant("READ", "db", "it's"),
+            r#"GRANT READ ON "db" TO "it's""#
+        );
+        assert_eq!(
+            select_owners(r#"zenoh_db_"x"#, "#owner"),
+            r##"SELECT "owner" FROM "zenoh_db_\"x".."#owner""##
+        );
+    }
+
+    #[test]
+    fn regex_escaping() {
+        assert_eq!(regex("", &["a/b"]), r"/^a\/b$/");
+        assert_eq!(
+            regex("", &["a.b+c/(d)|[e]{f}^g"]),
+            r"/^a\.b\+c\/\(d\)\|\[e\]\{f\}\^g$/"
+        );
+        assert_eq!(
+            select_last_timestamps(&["a\\b\nc".into(), "x/y.z".into()]),
+            r#"SELECT LAST("timestamp") FROM /^(?:a\\b\nc|x\/y\.z)$/"#
+        );
+        assert_eq!(
+            drop_all_series("", "#t", "#q"),
+            r##"DROP SERIES FROM /^[^#]*$/; DROP SERIES FROM "#q" WHERE "key" =~ /^[^#]*$/; DROP SERIES FROM "#t" WHERE "measurement" =~ /^[^#]*$/"##
+        );
+        assert_eq!(
+            drop_all_series("a.b#", "#t", "#q"),
+            r##"DROP SERIES FROM /^a\.b#[^#]*$/; DROP SERIES FROM "#q" WHERE "key" =~ /^a\.b#[^#]*$/; DROP SERIES FROM "#t" WHERE "measurement" =~ /^a\.b#[^#]*$/"##
+        );
+        assert_eq!(
+            show_quarantined_keys_cardinality("#q", "a.b#"),
+            r##"SHOW TAG VALUES EXACT CARDINALITY FROM "#q" WITH KEY = "key" WHERE "key" =~ /^a\.b#[^#]*$/"##
+        );
+    }
+
+    #[test]
+    fn regex_wildcards() {
+        assert_eq!(regex("", &["*"]), r"/^[^\/#]*$/");
+        assert_eq!(regex("", &["**"]), r"/^[^#]*$/");
+        assert_eq!(regex("", &["a/*"]), r"/^a\/[^\/#]*$/");
+        assert_eq!(regex("", &["a/**"]), r"/^a\/[^#]*$/");
+        assert_eq!(regex("", &["*/b"]), r"/^[^\/#]*\/b$/");
+        assert_eq!(regex("", &["a/*/c"]), r"/^a\/[^\/#]*\/c$/");
+        assert_eq!(regex("", &["a/**/c"]), r"/^a\/[^#]*\/c$/");
+        assert_eq!(regex("", &["a/x$*"]), r"/^a\/x[^\/#]*$/");
+        assert_eq!(regex("", &["a/$*x$*/c"]), r"/^a\/[^\/#]*x[^\/#]*\/c$/");
+    }
+
+    #[test]
+    fn measurements_paging() {
+        assert_eq!(
+            show_measurements("", 10, 0, None),
+            r"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^[^#]*$/ LIMIT 10"
+        );
+        assert_eq!(
+            show_measurements("n.s#", 10, 0, None),
+            r"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^n\.s#[^#]*$/ LIMIT 10"
+        );
+        assert_eq!(
+            show_measurements("", 10, 0, Some("a/b")),
+            r"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^(?:[\x{62}-\x{10ffff}][^#]*|a(?:[\x{30}-\x{10ffff}][^#]*|\/(?:[\x{63}-\x{10ffff}][^#]*|b[^#]+)))$/ LIMIT 10"
+        );
+        assert_eq!(
+            show_measurements("ns#", 10, 0, Some("ns#a")),
+            r"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^ns#(?:[\x{62}-\x{10ffff}][^#]*|a[^#]+)$/ LIMIT 10"
+        );
+        // '#' is never greater than a character
+        assert_eq!(
+            show_measurements("", 10, 0, Some("!")),
+            r"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^(?:[\x{22}-\x{22}\x{24}-\x{10ffff}][^#]*|![^#]+)$/ LIMIT 10"
+        );
+        assert_eq!(
+            show_measurements("", 10, 0, Some("\"")),
+            r#"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^(?:[\x{24}-\x{10ffff}][^#]*|"[^#]+)$/ LIMIT 10"#
+        );
+        assert_eq!(
+            show_measurements("", 10, 20, Some("a")),
+            r"SHOW MEASUREMENTS WITH MEASUREMENT =~ /^(?:[\x{62}-\x{10ffff}][^#]*|a[^#]+)$/ LIMIT 10 OFFSET 20"
+        );
+        assert_eq!(
+            show_tombstoned_measurements("#t", "ns#", 10, 0, Some("ns#a")),
+            r##"SHOW TAG VALUES FROM "#t" WITH KEY = "measurement" WHERE "measurement" =~ /^ns#(?:[\x{62}-\x{10ffff}][^#]*|a[^#]+)$/ LIMIT 10"##
+        );
+        assert_eq!(
+            select_tombstones("#t", &["ns#a".into(), "ns#b.c".into()]),
+            r##"SELECT LAST("timestamp") FROM "#t" WHERE "measurement" =~ /^(?:ns#a|ns#b\.c)$/ GROUP BY "measurement""##
+        );
+        let long = "a".repeat(MAX_FOLLOWING_CHARS + 1);
+        let closing = ")".repeat(MAX_FOLLOWING_CHARS);
+        assert!(show_measurements("", 10, 0, Some(&long))
+            .ends_with(&format!("a[^#]*{closing}$/ LIMIT 10")));
+    }
+
+    #[test]
+    fn regex_prefix_and_alternatives() {
+        assert_eq!(regex("ns#", &["a/*"]), r"/^ns#a\/[^\/#]*$/");
+        assert_eq!(regex("n.s#", &["a", "b/**"]), r"/^n\.s#a$|^n\.s#b\/[^#]*$/");
+    }
+}
@@ -0,0 +1,1045 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Guards and filters applied by a storage to the samples it receives, before writing them to InfluxDB.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use zenoh::buffers::SplitBuffer;
+use zenoh::prelude::{keyexpr, OwnedKeyExpr, Value};
+use zenoh::time::{Timestamp, NTP64};
+
+// min period between 2 warnings of a same kind
+const LOG_PERIOD: Duration = Duration::from_secs(10);
+
+// max number of keys whose state is kept in memory by a filter
+pub(crate) const MAX_TRACKED_KEYS: usize = 100_000;
+
+// Insert the state of a key in a map of at most MAX_TRACKED_KEYS keys, evicting an arbitrary key if it's full.
+// An evicted key is just tracked again from its next sample, as if it was new.
+fn insert_bounded<V>(map: &mut HashMap<String, V>, key: &str, value: V) {
+    if map.len() >= MAX_TRACKED_KEYS && !map.contains_key(key) {
+        if let Some(evicted) = map.keys().next().cloned() {
+            map.remove(&evicted);
+        }
+    }
+    map.insert(key.into(), value);
+}
+
+// The measurement storing the quarantined samples, tagged with the measurement of their key.
+// As '#' is forbidden in key expressions, it never matches a key.
+pub(crate) const QUARANTINE_MEASUREMENT: &str = "#quarantine";
+
+/// A warning logged at most once per period, with the number of suppressed ones.
+pub(crate) struct RateLimitedLog {
+    last: Mutex<Option<Instant>>,
+    suppressed: AtomicU64,
+}
+
+impl RateLimitedLog {
+    pub(crate) fn new() -> Self {
+        RateLimitedLog {
+            last: Mutex::new(None),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn warn<F: FnOnce() -> String>(&self, message: F) {
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(_) => return,
+        };
+        match *last {
+            Some(t) if t.elapsed() < LOG_PERIOD => {
+                self.suppressed.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                *last = Some(Instant::now());
+                let suppressed = self.suppressed.swap(0, Ordering::Relaxed);
+                if suppressed > 0 {
+                    warn!("{} ({} similar messages suppressed)", message(), suppressed);
+                } else {
+                    warn!("{}", message());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FutureDriftAction {
+    // the sample is ignored
+    #[default]
+    Reject,
+    // the sample is stored with the current time
+    Clamp,
+    // the sample is stored in a separate measurement, not visible by the GETs
+    Quarantine,
+}
+
+/// The outcome of the clock-skew guard for a sample.
+pub(crate) enum Admission {
+    // store the sample with this timestamp
+    Accept(Timestamp),
+    // store the sample in quarantine
+    Quarantine,
+    // ignore the sample
+    Reject,
+}
+
+/// Guard against the samples with timestamps too far in the future (i.e. from a publisher with a broken clock).
+pub(crate) struct ClockSkewGuard {
+    max_drift: Duration,
+    action: FutureDriftAction,
+    rejected: AtomicU64,
+    clamped: AtomicU64,
+    quarantined: AtomicU64,
+    log: RateLimitedLog,
+}
+
+impl ClockSkewGuard {
+    pub(crate) fn new(max_drift: Duration, action: FutureDriftAction) -> Self {
+        ClockSkewGuard {
+            max_drift,
+            action,
+            rejected: AtomicU64::new(0),
+            clamped: AtomicU64::new(0),
+            quarantined: AtomicU64::new(0),
+            log: RateLimitedLog::new(),
+        }
+    }
+
+    /// Check the timestamp of a sample for a key. Deletions can't be quarantined, thus are rejected instead.
+    pub(crate) fn check(&self, key: &str, timestamp: Timestamp, is_delete: bool) -> Admission {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let time = timestamp.get_time().to_duration();
+        // a max drift overflowing the time doesn't limit the timestamps
+        if now
+            .checked_add(self.max_drift)
+            .map_or(true, |limit| time <= limit)
+        {
+            return Admission::Accept(timestamp);
+        }
+        let action = match self.action {
+            FutureDriftAction::Quarantine if is_delete => FutureDriftAction::Reject,
+            action => action,
+        };
+        self.log.warn(|| {
+            format!(
+                "Received a {} for {} with timestamp {} in the future (more than {:?} after now) from {}: {:?}",
+                if is_delete { "DELETE" } else { "PUT" },
+                key,
+                timestamp,
+                self.max_drift,
+                timestamp.get_id(),
+                action
+            )
+        });
+        match action {
+            FutureDriftAction::Reject => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                Admission::Reject
+            }
+            FutureDriftAction::Clamp => {
+                self.clamped.fetch_add(1, Ordering::Relaxed);
+                Admission::Accept(Timestamp::new(NTP64::from(now), *timestamp.get_id()))
+            }
+            FutureDriftAction::Quarantine => {
+                self.quarantined.fetch_add(1, Ordering::Relaxed);
+                Admission::Quarantine
+            }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({
+            "rejected": self.rejected.load(Ordering::Relaxed),
+            "clamped": self.clamped.load(Ordering::Relaxed),
+            "quarantined": self.quarantined.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// The SHA-256 digest of a value (its encoding and payload).
+pub(crate) type Digest = [u8; 32];
+
+// The last value written for a key
+struct LastValue {
+    digest: Digest,
+    // the InfluxDB time of its point
+    time: u128,
+}
+
+/// Filter skipping the samples with the same value than the last one written for their key.
+/// Note: the PUTs on a storage being sequential (`&mut self`), a sample can't be checked
+/// while the previous one of its key is being written.
+/// The last values are cached in memory, seeded from InfluxDB on the 1st PUT of each key.
+pub(crate) struct ChangeFilter {
+    // the max period without write for a key, even if its value doesn't change
+    heartbeat: Option<Duration>,
+    // None if the key has no value (never put, or deleted)
+    last: Mutex<HashMap<String, Option<LastValue>>>,
+    skipped: AtomicU64,
+}
+
+impl ChangeFilter {
+    pub(crate) fn new(heartbeat: Option<Duration>) -> Self {
+        ChangeFilter {
+            heartbeat,
+            last: Mutex::new(HashMap::new()),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// The digest of a value (its encoding and payload), to be compared with the last one of its key.
+    pub(crate) fn digest(encoding_prefix: u8, encoding_suffix: &str, payload: &[u8]) -> Digest {
+        let mut hasher = Sha256::new();
+        hasher.update([encoding_prefix]);
+        // the suffix length, not to confuse its end with the payload's start
+        hasher.update((encoding_suffix.len() as u64).to_le_bytes());
+        hasher.update(encoding_suffix.as_bytes());
+        hasher.update(payload);
+        hasher.finalize().into()
+    }
+
+    /// Returns true if no more keys can be cached without evicting another one.
+    pub(crate) fn is_full(&self) -> bool {
+        match self.last.lock() {
+            Ok(last) => last.len() >= MAX_TRACKED_KEYS,
+            Err(_) => true,
+        }
+    }
+
+    /// Cache the last value of a key, as read from InfluxDB (digest and time of its point),
+    /// unless already cached.
+    pub(crate) fn seed(&self, key: &str, value: Option<(Digest, u128)>) {
+        if let Ok(mut last) = self.last.lock() {
+            if !last.contains_key(key) {
+                let value = value.map(|(digest, time)| LastValue { digest, time });
+                insert_bounded(&mut last, key, value);
+            }
+        }
+    }
+
+    /// Returns true if a value at a time is the same than the last one of its key,
+    /// and if the heartbeat period is not elapsed since the last one was written.
+    /// Returns None if the last value of the key is not cached: it must be read from InfluxDB (see `seed_and_check`).
+    pub(crate) fn is_unchanged(&self, key: &str, digest: &Digest, time: u128) -> Option<bool> {
+        let last = match self.last.lock() {
+            Ok(last) => last,
+            Err(_) => return Some(false),
+        };
+        let unchanged = self.compare(last.get(key)?.as_ref(), digest, time);
+        Some(unchanged)
+    }
+
+    /// Cache the last value of a key as read from InfluxDB (unless cached meanwhile), and check a value against it
+    /// (see `is_unchanged`), at once.
+    pub(crate) fn seed_and_check(
+        &self,
+        key: &str,
+        value: Option<(Digest, u128)>,
+        digest: &Digest,
+        time: u128,
+    ) -> bool {
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(_) => return false,
+        };
+        if !last.contains_key(key) {
+            let value = value.map(|(digest, time)| LastValue { digest, time });
+            insert_bounded(&mut last, key, value);
+        }
+        self.compare(last.get(key).and_then(Option::as_ref), digest, time)
+    }
+
+    fn compare(&self, last: Option<&LastValue>, digest: &Digest, time: u128) -> bool {
+        let unchanged = match last {
+            // an older sample is always written, being a distinct point of the history
+            Some(last) if last.digest == *digest && time >= last.time => match self.heartbeat {
+                Some(heartbeat) => time - last.time < heartbeat.as_nanos(),
+                None => true,
+            },
+            _ => false,
+        };
+        if unchanged {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        unchanged
+    }
+
+    pub(crate) fn on_written(&self, key: &str, digest: Digest, time: u128) {
+        if let Ok(mut last) = self.last.lock() {
+            match last.get(key) {
+                Some(Some(last)) if last.time > time => (),
+                _ => insert_bounded(&mut last, key, Some(LastValue { digest, time })),
+            }
+        }
+    }
+
+    pub(crate) fn on_deleted(&self, key: &str) {
+        if let Ok(mut last) = self.last.lock() {
+            insert_bounded(&mut last, key, None);
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let cached_keys = match self.last.lock() {
+            Ok(last) => last.len(),
+            Err(_) => 0,
+        };
+        json!({
+            "heartbeat": self.heartbeat.map(|h| humantime::format_duration(h).to_string()),
+            "cached_keys": cached_keys,
+            "skipped": self.skipped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// The value as a number, if its payload is a number as text (whatever its encoding).
+pub(crate) fn parse_number(value: &Value) -> Option<f64> {
+    let payload = value.payload.contiguous();
+    let number = std::str::from_utf8(&payload)
+        .ok()?
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    number.is_finite().then_some(number)
+}
+
+/// The threshold of a deadband, under which a new value is not written.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Deadband {
+    // the absolute difference with the last written value
+    Absolute(f64),
+    // the difference relative to the last written value, in percent
+    Percent(f64),
+}
+
+impl Deadband {
+    fn contains(&self, last: f64, value: f64) -> bool {
+        let threshold = match self {
+            Deadband::Absolute(threshold) => *threshold,
+            Deadband::Percent(percent) => last.abs() * percent / 100.0,
+        };
+        (value - last).abs() <= threshold
+    }
+}
+
+/// A deadband, applying to the numeric values of the keys matching a key expression.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct DeadbandRule {
+    // the keys the rule applies to (including the storage's `strip_prefix`)
+    #[serde(serialize_with = "crate::settings::serialize_key_expr")]
+    pub(crate) key_expr: OwnedKeyExpr,
+    #[serde(flatten)]
+    pub(crate) deadband: Deadband,
+    // the max period without write for a key, even if its value stays in the deadband
+    #[serde(serialize_with = "crate::settings::serialize_opt_duration")]
+    pub(crate) max_interval: Option<Duration>,
+}
+
+/// Filter skipping the numeric values within the deadband of the last value written for their key.
+pub(crate) struct DeadbandFilter {
+    // the 1st rule matching a key applies
+    rules: Vec<DeadbandRule>,
+    // the last value written for each key, with the InfluxDB time of its point
+    last: Mutex<HashMap<String, (f64, u128)>>,
+    skipped: AtomicU64,
+}
+
+impl DeadbandFilter {
+    pub(crate) fn new(rules: Vec<DeadbandRule>) -> Self {
+        DeadbandFilter {
+            rules,
+            last: Mutex::new(HashMap::new()),
+            skipped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns true if a key is subject to a deadband rule.
+    pub(crate) fn applies_to(&self, full_key: &keyexpr) -> bool {
+        self.rule(full_key).is_some()
+    }
+
+    fn rule(&self, full_key: &keyexpr) -> Option<&DeadbandRule> {
+        self.rules.iter().find(|r| r.key_expr.includes(full_key))
+    }
+
+    /// Returns true if a numeric value at a time is within the deadband of the last value written for its key,
+    /// and if the rule's max interval is not elapsed since it was written.
+    pub(crate) fn is_within(&self, full_key: &keyexpr, key: &str, value: f64, time: u128) -> bool {
+        let rule = match self.rule(full_key) {
+            Some(rule) => rule,
+            None => return false,
+        };
+        let last = match self.last.lock() {
+            Ok(last) => last,
+            Err(_) => return false,
+        };
+        let within = match last.get(key) {
+            // an older sample is always written, being a distinct point of the history
+            Some((last_value, last_time)) if time >= *last_time => {
+                rule.deadband.contains(*last_value, value)
+                    && rule
+                        .max_interval
+                        .map_or(true, |max| time - last_time < max.as_nanos())
+            }
+            _ => false,
+        };
+        if within {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        within
+    }
+
+    pub(crate) fn on_written(&self, key: &str, value: f64, time: u128) {
+        if let Ok(mut last) = self.last.lock() {
+            match last.get(key) {
+                Some((_, last_time)) if *last_time > time => (),
+                _ => insert_bounded(&mut last, key, (value, time)),
+            }
+        }
+    }
+
+    pub(crate) fn on_deleted(&self, key: &str) {
+        if let Ok(mut last) = self.last.lock() {
+            last.remove(key);
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let tracked_keys = match self.last.lock() {
+            Ok(last) => last.len(),
+            Err(_) => 0,
+        };
+        json!({
+            "tracked_keys": tracked_keys,
+            "skipped": self.skipped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitMode {
+    // the samples arriving faster than the min interval are dropped
+    #[default]
+    Drop,
+    // the numeric samples arriving faster than the min interval are averaged into the next written one
+    Average,
+}
+
+/// A min interval between the samples written for each key matching a key expression.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RateLimitRule {
+    // the keys the rule applies to (including the storage's `strip_prefix`)
+    #[serde(serialize_with = "crate::settings::serialize_key_expr")]
+    pub(crate) key_expr: OwnedKeyExpr,
+    #[serde(serialize_with = "crate::settings::serialize_duration")]
+    pub(crate) min_interval: Duration,
+    pub(crate) mode: RateLimitMode,
+}
+
+/// The decision of the rate limiter for a sample.
+pub(crate) enum RateDecision {
+    // write the sample
+    Write,
+    // write the average of the sample with the previous ones, in place of its value (as a float)
+    WriteAverage(f64),
+    // don't write the sample
+    Drop,
+}
+
+// The current window of a key: from the time of its last written sample
+struct RateWindow {
+    start: u128,
+    // the numeric values received in the window, to be averaged
+    sum: f64,
+    count: u64,
+}
+
+impl RateWindow {
+    fn new(start: u128) -> Self {
+        RateWindow {
+            start,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+/// Limiter of the rate of the samples written for each key, according to the time of the samples.
+pub(crate) struct RateLimiter {
+    // the 1st rule matching a key applies
+    rules: Vec<RateLimitRule>,
+    windows: Mutex<HashMap<String, RateWindow>>,
+    dropped: AtomicU64,
+    averaged: AtomicU64,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rules: Vec<RateLimitRule>) -> Self {
+        RateLimiter {
+            rules,
+            windows: Mutex::new(HashMap::new()),
+            dropped: AtomicU64::new(0),
+            averaged: AtomicU64::new(0),
+        }
+    }
+
+    /// Decide if a sample at a time is written, dropped or averaged, according to the rule matching its key.
+    pub(crate) fn check(
+        &self,
+        full_key: &keyexpr,
+        key: &str,
+        value: &Value,
+        time: u128,
+    ) -> RateDecision {
+        let rule = match self.rule(full_key) {
+            Some(rule) => rule,
+            None => return RateDecision::Write,
+        };
+        let mut windows = match self.windows.lock() {
+            Ok(windows) => windows,
+            Err(_) => return RateDecision::Write,
+        };
+        // the window of a key starts when a sample is written (see `on_written`)
+        let window = match windows.get_mut(key) {
+            Some(window) => window,
+            None => return RateDecision::Write,
+        };
+        // an older sample is always written, being a distinct point of the history
+        if time < window.start {
+            return RateDecision::Write;
+        }
+        let number = match rule.mode {
+            RateLimitMode::Average => parse_number(value),
+            RateLimitMode::Drop => None,
+        };
+        if time - window.start >= rule.min_interval.as_nanos() {
+            return match number {
+                Some(number) if window.count > 0 => {
+                    RateDecision::WriteAverage((window.sum + number) / (window.count + 1) as f64)
+                }
+                _ => RateDecision::Write,
+            };
+        }
+        match number {
+            Some(number) => {
+                window.sum += number;
+                window.count += 1;
+                self.averaged.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        RateDecision::Drop
+    }
+
+    fn rule(&self, full_key: &keyexpr) -> Option<&RateLimitRule> {
+        self.rules.iter().find(|r| r.key_expr.includes(full_key))
+    }
+
+    /// Start a new window for a key from the time of its written sample (unless older than the current one).
+    pub(crate) fn on_written(&self, full_key: &keyexpr, key: &str, time: u128) {
+        if self.rule(full_key).is_none() {
+            return;
+        }
+        if let Ok(mut windows) = self.windows.lock() {
+            match windows.get(key) {
+                Some(window) if window.start > time => (),
+                _ => insert_bounded(&mut windows, key, RateWindow::new(time)),
+            }
+        }
+    }
+
+    /// Discard the samples of the window of a key closed by a sample at a time, which wasn't written
+    /// (e.g. skipped by another filter), not to average them with the samples of the next window.
+    pub(crate) fn on_skipped(&self, full_key: &keyexpr, key: &str, time: u128) {
+        let rule = match self.rule(full_key) {
+            Some(rule) => rule,
+            None => return,
+        };
+        if let Ok(mut windows) = self.windows.lock() {
+            if let Some(window) = windows.get_mut(key) {
+                if time >= window.start && time - window.start >= rule.min_interval.as_nanos() {
+                    window.sum = 0.0;
+                    window.count = 0;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn on_deleted(&self, key: &str) {
+        if let Ok(mut windows) = self.windows.lock() {
+            windows.remove(key);
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "averaged": self.averaged.load(Ordering::Relaxed),
+        })
+    }
+}
+
+// min period between 2 counts of the keys of a storage (or of the points of a key), when its quota is reached
+const COUNT_PERIOD: Duration = Duration::from_secs(10);
+
+// The number of points of a key, and when it was counted in InfluxDB
+#[derive(Clone, Copy)]
+struct PointCount {
+    count: u64,
+    counted: Instant,
+}
+
+/// The quotas of a storage.
+#[derive(Clone, Debug, Default, Serialize)]
+pub(crate) struct QuotaSettings {
+    // the max number of keys (i.e. measurements)
+    pub(crate) max_keys: Option<u64>,
+    // the max number of points per key (including the deletion markers)
+    pub(crate) max_points_per_key: Option<u64>,
+    // the max size of a payload, in bytes
+    pub(crate) max_bytes_per_payload: Option<usize>,
+}
+
+/// Enforcement of the quotas of a storage, protecting InfluxDB against an unbounded number of series.
+pub(crate) struct Quotas {
+    storage: String,
+    settings: QuotaSettings,
+    // the number of points of the keys known to exist (0 if known to not exist)
+    points: Mutex<HashMap<String, PointCount>>,
+    // the keys without points but with quarantined samples (counted in the number of keys)
+    quarantined: Mutex<HashMap<String, ()>>,
+    // the number of keys in InfluxDB, and when it was counted
+    key_count: Mutex<Option<(u64, Instant)>>,
+    rejected_keys: AtomicU64,
+    rejected_points: AtomicU64,
+    rejected_payloads: AtomicU64,
+    log: RateLimitedLog,
+}
+
+impl Quotas {
+    pub(crate) fn new(storage: &str, settings: QuotaSettings) -> Self {
+        Quotas {
+            storage: storage.into(),
+            settings,
+            points: Mutex::new(HashMap::new()),
+            quarantined: Mutex::new(HashMap::new()),
+            key_count: Mutex::new(None),
+            rejected_keys: AtomicU64::new(0),
+            rejected_points: AtomicU64::new(0),
+            rejected_payloads: AtomicU64::new(0),
+            log: RateLimitedLog::new(),
+        }
+    }
+
+    /// Returns true if a payload size is within the quota.
+    pub(crate) fn check_payload(&self, key: &str, size: usize) -> bool {
+        match self.settings.max_bytes_per_payload {
+            Some(max) if size > max => {
+                self.rejected_payloads.fetch_add(1, Ordering::Relaxed);
+                self.log.warn(|| {
+                    format!(
+                        "Storage {}: reject PUT on {} with a payload of {} bytes, exceeding the `max_bytes_per_payload` quota ({})",
+                        self.storage, key, size, max
+                    )
+                });
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns true if the number of keys is limited.
+    pub(crate) fn limits_keys(&self) -> bool {
+        self.settings.max_keys.is_some()
+    }
+
+    /// Returns true if the number of points of the keys has to be known.
+    pub(crate) fn counts_points(&self) -> bool {
+        self.settings.max_keys.is_some() || self.settings.max_points_per_key.is_some()
+    }
+
+    /// The number of points of a key, or None if it has to be counted in InfluxDB
+    /// (never counted, or the quota is reached and the count might be outdated,
+    /// e.g. after the removal of points by a retention policy).
+    pub(crate) fn points(&self, key: &str) -> Option<u64> {
+        let points = *self.points.lock().ok()?.get(key)?;
+        match self.settings.max_points_per_key {
+            Some(max) if points.count >= max && points.counted.elapsed() >= COUNT_PERIOD => None,
+            _ => Some(points.count),
+        }
+    }
+
+    /// Cache the number of points of a key, as counted in InfluxDB.
+    pub(crate) fn set_points(&self, key: &str, count: u64) {
+        if let Ok(mut known) = self.points.lock() {
+            let points = PointCount {
+                count,
+                counted: Instant::now(),
+            };
+            insert_bounded(&mut known, key, points);
+        }
+    }
+
+    /// The number of keys, or None if it has to be counted in InfluxDB
+    /// (never counted, or the quota is reached and the count might be outdated).
+    pub(crate) fn key_count(&self) -> Option<u64> {
+        let max = self.settings.max_keys?;
+        match *self.key_count.lock().ok()? {
+            Some((count, time)) if count < max || time.elapsed() < COUNT_PERIOD => Some(count),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_key_count(&self, count: u64) {
+        if let Ok(mut key_count) = self.key_count.lock() {
+            *key_count = Some((count, Instant::now()));
+        }
+    }
+
+    /// Returns true if a new key is within the keys quota, given the number of keys (if limited).
+    pub(crate) fn check_new_key(&self, key: &str, key_count: Option<u64>) -> bool {
+        match (self.settings.max_keys, key_count) {
+            (Some(max), Some(count)) if count >= max => {
+                self.rejected_keys.fetch_add(1, Ordering::Relaxed);
+                self.log.warn(|| {
+                    format!(
+                        "Storage {}: reject PUT on new key {}, the `max_keys` quota ({}) being reached",
+                        self.storage, key, max
+                    )
+                });
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns true if a PUT on a key with a number of points (0 for a new key) is within the quotas.
+    pub(crate) fn check_put(&self, key: &str, points: u64, key_count: Option<u64>) -> bool {
+        if points == 0 && !self.check_new_key(key, key_count) {
+            return false;
+        }
+        match self.settings.max_points_per_key {
+            Some(max) if points >= max => {
+                self.rejected_points.fetch_add(1, Ordering::Relaxed);
+                self.log.warn(|| {
+                    format!(
+                        "Storage {}: reject PUT on key {}, its `max_points_per_key` quota ({}) being reached",
+                        self.storage, key, max
+                    )
+                });
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Update the number of points of a key after a PUT, which replaced its previous points or not.
+    pub(crate) fn on_written(&self, key: &str, replaced: bool) {
+        let mut known = match self.points.lock() {
+            Ok(known) => known,
+            Err(_) => return,
+        };
+        let mut points = known.get(key).copied().unwrap_or(PointCount {
+            count: 0,
+            counted: Instant::now(),
+        });
+        // a key with quarantined samples is already counted
+        let quarantined = self
+            .quarantined
+            .lock()
+            .map_or(false, |mut quarantined| quarantined.remove(key).is_some());
+        if points.count == 0 && !quarantined {
+            if let Ok(mut key_count) = self.key_count.lock() {
+                if let Some((count, _)) = key_count.as_mut() {
+                    *count += 1;
+                }
+            }
+        }
+        points.count = if replaced { 1 } else { points.count + 1 };
+        insert_bounded(&mut known, key, points);
+    }
+
+    /// Returns true if a key without points has quarantined samples, thus is already counted in the number of keys.
+    pub(crate) fn is_quarantined(&self, key: &str) -> bool {
+        self.quarantined
+            .lock()
+            .map_or(false, |quarantined| quarantined.contains_key(key))
+    }
+
+    /// Update the number of keys after a quarantined sample on a key, if it has no points.
+    pub(crate) fn on_quarantined(&self, key: &str) {
+        let has_points = match self.points.lock() {
+            Ok(known) => known.get(key).map_or(false, |points| points.count > 0),
+            Err(_) => return,
+        };
+        if has_points {
+            return;
+        }
+        if let Ok(mut quarantined) = self.quarantined.lock() {
+            if quarantined.contains_key(key) {
+                return;
+            }
+            insert_bounded(&mut quarantined, key, ());
+        }
+        if let Ok(mut key_count) = self.key_count.lock() {
+            if let Some((count, _)) = key_count.as_mut() {
+                *count += 1;
+            }
+        }
+    }
+
+    /// Update the number of points of a key after a DELETE (only its deletion marker remains).
+    pub(crate) fn on_deleted(&self, key: &str) {
+        if let Ok(mut known) = self.points.lock() {
+            if let Some(points) = known.get_mut(key) {
+                points.count = 1;
+            }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let (keys, counted_at) = match self.key_count.lock() {
+            Ok(key_count) => match *key_count {
+                Some((count, time)) => (Some(count), Some(time.elapsed())),
+                None => (None, None),
+            },
+            Err(_) => (None, None),
+        };
+        json!({
+            "keys": keys,
+            "keys_counted_ago": counted_at.map(|d| humantime::format_duration(Duration::from_secs(d.as_secs())).to_string()),
+            "rejected_keys": self.rejected_keys.load(Ordering::Relaxed),
+            "rejected_points": self.rejected_points.load(Ordering::Relaxed),
+            "rejected_payloads": self.rejected_payloads.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use zenoh::time::new_reception_timestamp;
+
+    fn now() -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+    }
+
+    fn timestamp(time: Duration) -> Timestamp {
+        Timestamp::new(NTP64::from(time), *new_reception_timestamp().get_id())
+    }
+
+    fn key_expr(s: &str) -> &keyexpr {
+        keyexpr::new(s).unwrap()
+    }
+
+    #[test]
+    fn clock_skew_clamp() {
+        let guard = ClockSkewGuard::new(Duration::from_secs(60), FutureDriftAction::Clamp);
+        let near = timestamp(now() + Duration::from_secs(30));
+        assert!(matches!(guard.check("a", near, false), Admission::Accept(t) if t == near));
+        let before = now();
+        let far = timestamp(now() + Duration::from_secs(3600));
+        match guard.check("a", far, false) {
+            Admission::Accept(t) => {
+                let time = t.get_time().to_duration();
+                assert!(time + Duration::from_millis(1) >= before);
+                assert!(time <= now() + Duration::from_millis(1));
+                assert_eq!(t.get_id(), far.get_id());
+            }
+            _ => panic!("a far-future timestamp must be clamped"),
+        }
+        assert_eq!(guard.to_json()["clamped"], 1);
+
+        let guard = ClockSkewGuard::new(Duration::from_secs(60), FutureDriftAction::Quarantine);
+        assert!(matches!(
+            guard.check("a", far, false),
+            Admission::Quarantine
+        ));
+        // a deletion can't be quarantined
+        assert!(matches!(guard.check("a", far, true), Admission::Reject));
+
+        // the max drift doesn't overflow
+        let guard = ClockSkewGuard::new(Duration::MAX, FutureDriftAction::Reject);
+        assert!(matches!(guard.check("a", far, false), Admission::Accept(_)));
+    }
+
+    #[test]
+    fn change_filter_heartbeat() {
+        let filter = ChangeFilter::new(Some(Duration::from_secs(10)));
+        let digest = ChangeFilter::digest(0, "", b"1");
+        let other = ChangeFilter::digest(0, "", b"2");
+        let second = Duration::from_secs(1).as_nanos();
+        assert_eq!(filter.is_unchanged("a", &digest, 0), None);
+        filter.on_written("a", digest, 0);
+        assert_eq!(filter.is_unchanged("a", &digest, 5 * second), Some(true));
+        assert_eq!(filter.is_unchanged("a", &other, 5 * second), Some(false));
+        // the heartbeat period is elapsed
+        assert_eq!(filter.is_unchanged("a", &digest, 10 * second), Some(false));
+        filter.on_written("a", digest, 10 * second);
+        // an older sample is written
+        assert_eq!(filter.is_unchanged("a", &digest, 5 * second), Some(false));
+        // a seed doesn't replace a cached value
+        filter.seed("a", Some((other, 10 * second)));
+        assert_eq!(filter.is_unchanged("a", &digest, 11 * second), Some(true));
+        filter.on_deleted("a");
+        assert_eq!(filter.is_unchanged("a", &digest, 11 * second), Some(false));
+        assert_eq!(filter.to_json()["skipped"], 2);
+    }
+
+    #[test]
+    fn change_filter_bounded() {
+        let filter = ChangeFilter::new(None);
+        let digest = ChangeFilter::digest(0, "", b"1");
+        for i in 0..MAX_TRACKED_KEYS {
+            filter.on_written(&i.to_string(), digest, 0);
+        }
+        assert!(filter.is_full());
+        filter.on_written("new", digest, 0);
+        assert_eq!(filter.last.lock().unwrap().len(), MAX_TRACKED_KEYS);
+        assert_eq!(filter.is_unchanged("new", &digest, 0), Some(true));
+    }
+
+    #[test]
+    fn deadband_percent() {
+        let filter = DeadbandFilter::new(vec![DeadbandRule {
+            key_expr: OwnedKeyExpr::from_str("a/**").unwrap(),
+            deadband: Deadband::Percent(10.0),
+            max_interval: Some(Duration::from_nanos(100)),
+        }]);
+        assert!(filter.applies_to(key_expr("a/b")));
+        assert!(!filter.applies_to(key_expr("b")));
+        filter.on_written("a/b", 50.0, 0);
+        assert!(filter.is_within(key_expr("a/b"), "a/b", 54.0, 10));
+        assert!(!filter.is_within(key_expr("a/b"), "a/b", 56.0, 10));
+        // the max interval is elapsed
+        assert!(!filter.is_within(key_expr("a/b"), "a/b", 50.0, 100));
+        // a percentage of 0 is 0: any change is written
+        filter.on_written("a/b", 0.0, 100);
+        assert!(filter.is_within(key_expr("a/b"), "a/b", 0.0, 110));
+        assert!(!filter.is_within(key_expr("a/b"), "a/b", 0.001, 110));
+        assert!(!filter.is_within(key_expr("a/b"), "a/b", -0.001, 110));
+    }
+
+    #[test]
+    fn rate_limiter_average() {
+        let limiter = RateLimiter::new(vec![RateLimitRule {
+            key_expr: OwnedKeyExpr::from_str("a/**").unwrap(),
+            min_interval: Duration::from_nanos(10),
+            mode: RateLimitMode::Average,
+        }]);
+        let key = key_expr("a/b");
+        // the window starts with a written sample
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("1"), 0),
+            RateDecision::Write
+        ));
+        limiter.on_written(key, "a/b", 0);
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("1"), 2),
+            RateDecision::Drop
+        ));
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("3"), 5),
+            RateDecision::Drop
+        ));
+        // a non-numeric sample is dropped, not averaged
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("x"), 6),
+            RateDecision::Drop
+        ));
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("5"), 10),
+            RateDecision::WriteAverage(average) if average == 3.0
+        ));
+        limiter.on_written(key, "a/b", 10);
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("1"), 12),
+            RateDecision::Drop
+        ));
+        // the window closed by a skipped sample is discarded
+        limiter.on_skipped(key, "a/b", 20);
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("5"), 21),
+            RateDecision::Write
+        ));
+        // an older sample is written
+        assert!(matches!(
+            limiter.check(key, "a/b", &Value::from("5"), 5),
+            RateDecision::Write
+        ));
+        assert_eq!(limiter.to_json()["averaged"], 3);
+        assert_eq!(limiter.to_json()["dropped"], 1);
+    }
+
+    #[test]
+    fn quotas_recount() {
+        let quotas = Quotas::new(
+            "test",
+            QuotaSettings {
+                max_keys: Some(2),
+                max_points_per_key: Some(3),
+                max_bytes_per_payload: Some(4),
+            },
+        );
+        let outdated = Instant::now().checked_sub(COUNT_PERIOD).unwrap();
+        assert!(quotas.check_payload("a", 4));
+        assert!(!quotas.check_payload("a", 5));
+
+        // the points of a key reaching its quota are counted again after a period
+        assert_eq!(quotas.points("a"), None);
+        quotas.set_points("a", 2);
+        assert!(quotas.check_put("a", 2, None));
+        quotas.on_written("a", false);
+        assert_eq!(quotas.points("a"), Some(3));
+        assert!(!quotas.check_put("a", 3, None));
+        quotas.points.lock().unwrap().get_mut("a").unwrap().counted = outdated;
+        assert_eq!(quotas.points("a"), None);
+        quotas.set_points("a", 1);
+        assert_eq!(quotas.points("a"), Some(1));
+
+        // the keys too, once their quota is reached
+        assert_eq!(quotas.key_count(), None);
+        quotas.set_key_count(1);
+        assert_eq!(quotas.key_count(), Some(1));
+        // a quarantined key is counted once
+        quotas.on_quarantined("b");
+        assert!(quotas.is_quarantined("b"));
+        quotas.on_written("b", false);
+        assert!(!quotas.is_quarantined("b"));
+        assert_eq!(quotas.key_count(), Some(2));
+        assert!(!quotas.check_new_key("c", Some(2)));
+        *quotas.key_count.lock().unwrap() = Some((2, outdated));
+        assert_eq!(quotas.key_count(), None);
+        quotas.set_key_count(1);
+        assert!(quotas.check_new_key("c", quotas.key_count()));
+    }
+}
@@ -0,0 +1,477 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Built-in transforms of the samples received by the storages, before they're stored,
+//! and conversions of the samples they reply with.
+
+use base64::{engine::general_purpose::STANDARD as b64_std_engine, Engine};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use uuid::Uuid;
+use zenoh::buffers::{SplitBuffer, ZBuf};
+use zenoh::prelude::*;
+use zenoh::Result as ZResult;
+use zenoh_core::bail;
+
+// Prefix of the encoding suffix marking a sample dropped by the interceptor
+const DROPPED_ENCODING_PREFIX: &str = "zenoh-backend-influxdb;dropped;";
+
+/// Substitution of a key prefix.
+#[derive(Debug, Serialize)]
+pub(crate) struct KeyRewrite {
+    pub(crate) from: String,
+    pub(crate) to: String,
+}
+
+impl KeyRewrite {
+    // The rewritten key, if it starts with the `from` prefix
+    fn apply(&self, key: &str) -> Option<String> {
+        let rest = key.strip_prefix(&self.from)?;
+        if rest.is_empty() {
+            Some(self.to.clone())
+        } else if rest.starts_with('/') {
+            Some(format!("{}{}", self.to, rest))
+        } else {
+            None
+        }
+    }
+}
+
+/// The transforms applied by the incoming data interceptor, in this order.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct IncomingTransforms {
+    // the 1st matching rewrite applies
+    pub(crate) key_rewrites: Vec<KeyRewrite>,
+    // the encodings of the samples to keep (all if None)
+    pub(crate) allowed_encodings: Option<Vec<String>>,
+    // the top-level fields to keep in JSON payloads (all if None)
+    pub(crate) json_fields: Option<Vec<String>>,
+    // the max size of the payloads to keep (after projection)
+    pub(crate) max_payload_size: Option<usize>,
+}
+
+impl IncomingTransforms {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.key_rewrites.is_empty()
+            && self.allowed_encodings.is_none()
+            && self.json_fields.is_none()
+            && self.max_payload_size.is_none()
+    }
+
+    fn is_allowed(&self, encoding: &Encoding) -> bool {
+        match &self.allowed_encodings {
+            Some(allowed) => {
+                let encoding = encoding.to_string();
+                allowed.iter().any(|a| {
+                    encoding == *a
+                        || encoding
+                            .strip_prefix(a.as_str())
+                            .map_or(false, |suffix| suffix.starts_with(';'))
+                })
+            }
+            None => true,
+        }
+    }
+
+    // The JSON payload restricted to the configured fields, or None if it's not a JSON object
+    fn project(&self, value: &Value) -> Option<Vec<u8>> {
+        let fields = self.json_fields.as_ref()?;
+        if !matches!(
+            value.encoding.prefix(),
+            KnownEncoding::AppJson | KnownEncoding::TextJson
+        ) {
+            return None;
+        }
+        let mut object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&value.payload.contiguous()).ok()?;
+        object.retain(|k, _| fields.contains(k));
+        serde_json::to_vec(&object).ok()
+    }
+}
+
+/// The incoming data interceptor of a volume, with its counters.
+pub(crate) struct Incoming {
+    transforms: IncomingTransforms,
+    // Encoding suffix marking a sample dropped by the interceptor (an interceptor can't discard a sample,
+    // so the storage discards it on reception). It ends with a random token, never exposed,
+    // thus a sample received from the network can't carry it.
+    dropped_marker: String,
+    rewritten_keys: AtomicU64,
+    projected_payloads: AtomicU64,
+    dropped_encoding: AtomicU64,
+    dropped_oversized: AtomicU64,
+}
+
+impl Incoming {
+    pub(crate) fn new(transforms: IncomingTransforms) -> Self {
+        Incoming {
+            transforms,
+            dropped_marker: format!("{DROPPED_ENCODING_PREFIX}{}", Uuid::new_v4().simple()),
+            rewritten_keys: AtomicU64::new(0),
+            projected_payloads: AtomicU64::new(0),
+            dropped_encoding: AtomicU64::new(0),
+            dropped_oversized: AtomicU64::new(0),
+        }
+    }
+
+    fn intercept(&self, mut sample: Sample) -> Sample {
+        let t = &self.transforms;
+        if let Some(key) = t
+            .key_rewrites
+            .iter()
+            .find_map(|r| r.apply(sample.key_expr.as_str()))
+        {
+            match OwnedKeyExpr::from_str(&key) {
+                Ok(key) => {
+                    sample.key_expr = key.into();
+                    self.rewritten_keys.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => debug!("Failed to rewrite key {} : {}", sample.key_expr, e),
+            }
+        }
+        // the transforms on payload don't apply to deletions
+        if sample.kind != SampleKind::Put {
+            return sample;
+        }
+        if !t.is_allowed(&sample.value.encoding) {
+            debug!(
+                "Drop sample for {} with encoding {} (not allowed)",
+                sample.key_expr, sample.value.encoding
+            );
+            self.dropped_encoding.fetch_add(1, Ordering::Relaxed);
+            return self.drop_sample(sample);
+        }
+        if let Some(projected) = t.project(&sample.value) {
+            sample.value.payload = ZBuf::from(projected);
+            self.projected_payloads.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(max) = t.max_payload_size {
+            if sample.value.payload.len() > max {
+                debug!(
+                    "Drop sample for {} with payload of {} bytes (max {})",
+                    sample.key_expr,
+                    sample.value.payload.len(),
+                    max
+                );
+                self.dropped_oversized.fetch_add(1, Ordering::Relaxed);
+                return self.drop_sample(sample);
+            }
+        }
+        sample
+    }
+
+    fn drop_sample(&self, mut sample: Sample) -> Sample {
+        sample.value = Value::empty().encoding(Encoding::WithSuffix(
+            KnownEncoding::Empty,
+            self.dropped_marker.clone().into(),
+        ));
+        sample
+    }
+
+    /// Returns true if the value is the one of a sample dropped by the interceptor.
+    pub(crate) fn is_dropped(&self, value: &Value) -> bool {
+        *value.encoding.prefix() == KnownEncoding::Empty
+            && value.encoding.suffix() == self.dropped_marker
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({
+            "transforms": self.transforms,
+            "rewritten_keys": self.rewritten_keys.load(Ordering::Relaxed),
+            "projected_payloads": self.projected_payloads.load(Ordering::Relaxed),
+            "dropped_encoding": self.dropped_encoding.load(Ordering::Relaxed),
+            "dropped_oversized": self.dropped_oversized.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// The incoming interceptor function, to be returned by the volume.
+pub(crate) fn incoming_interceptor(
+    incoming: &Arc<Incoming>,
+) -> Arc<dyn Fn(Sample) -> Sample + Send + Sync> {
+    let incoming = incoming.clone();
+    Arc::new(move |sample| incoming.intercept(sample))
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OutputFormat {
+    // the values are returned as stored
+    #[default]
+    Raw,
+    // the values are returned as text/plain (base64-encoded if not UTF-8)
+    Text,
+    // the values are wrapped in a JSON object with their metadata
+    Json,
+}
+
+impl OutputFormat {
+    fn convert(&self, mut sample: Sample) -> Sample {
+        match self {
+            OutputFormat::Raw => (),
+            OutputFormat::Text => {
+                let text = payload_to_string(&sample.value);
+                sample.value = Value::from(text).encoding(KnownEncoding::TextPlain.into());
+            }
+            OutputFormat::Json => {
+                let value = match sample.value.encoding.prefix() {
+                    KnownEncoding::AppJson | KnownEncoding::TextJson => {
+                        serde_json::from_slice(&sample.value.payload.contiguous()).ok()
+                    }
+                    _ => None,
+                }
+                .unwrap_or_else(|| payload_to_string(&sample.value).into());
+                let kind = match sample.kind {
+                    SampleKind::Put => "PUT",
+                    SampleKind::Delete => "DELETE",
+                };
+                let wrapped = json!({
+                    "value": value,
+                    "encoding": sample.value.encoding.to_string(),
+                    "timestamp": sample.timestamp.as_ref().map(|t| t.to_string()),
+                    "kind": kind,
+                });
+                sample.value =
+                    Value::from(wrapped.to_string()).encoding(KnownEncoding::AppJson.into());
+            }
+        }
+        sample
+    }
+}
+
+// The payload as a string, base64-encoded if not UTF-8
+fn payload_to_string(value: &Value) -> String {
+    match String::from_utf8(value.payload.contiguous().into_owned()) {
+        Ok(s) => s,
+        Err(err) => b64_std_engine.encode(err.into_bytes()),
+    }
+}
+
+/// The output format of a storage, for the keys it stores.
+pub(crate) struct OutgoingRoute {
+    key_expr: OwnedKeyExpr,
+    format: OutputFormat,
+}
+
+/// The outgoing data interceptor of a volume, converting the replies of its storages to their output format.
+#[derive(Default)]
+pub(crate) struct Outgoing {
+    // the routes are removed when dropped by their storage
+    routes: Mutex<Vec<Weak<OutgoingRoute>>>,
+    converted: AtomicU64,
+}
+
+impl Outgoing {
+    /// Register the output format of a storage (even raw). The returned route must be kept by the storage.
+    /// As a reply doesn't tell the storage it comes from, the storages with intersecting key expressions
+    /// must have the same output format.
+    pub(crate) fn register(
+        &self,
+        key_expr: OwnedKeyExpr,
+        format: OutputFormat,
+    ) -> ZResult<Arc<OutgoingRoute>> {
+        let mut routes = match self.routes.lock() {
+            Ok(routes) => routes,
+            Err(_) => bail!("Failed to register the output format of {}", key_expr),
+        };
+        routes.retain(|r| r.strong_count() > 0);
+        let conflict = routes
+            .iter()
+            .filter_map(|r| r.upgrade())
+            .find(|r| r.format != format && r.key_expr.intersects(&key_expr));
+        if let Some(conflict) = conflict {
+            bail!(
+                "Output format {:?} of {} conflicts with the format {:?} of another storage on {} (intersecting key expressions must have the same output format)",
+                format,
+                key_expr,
+                conflict.format,
+                conflict.key_expr
+            )
+        }
+        let route = Arc::new(OutgoingRoute { key_expr, format });
+        routes.push(Arc::downgrade(&route));
+        Ok(route)
+    }
+
+    /// Returns true if no storage converts its replies.
+    pub(crate) fn is_empty(&self) -> bool {
+        match self.routes.lock() {
+            Ok(routes) => routes
+                .iter()
+                .filter_map(|r| r.upgrade())
+                .all(|r| r.format == OutputFormat::Raw),
+            Err(_) => true,
+        }
+    }
+
+    fn format(&self, key_expr: &keyexpr) -> OutputFormat {
+        match self.routes.lock() {
+            Ok(routes) => routes
+                .iter()
+                .filter_map(|r| r.upgrade())
+                .find(|r| r.key_expr.includes(key_expr))
+                .map(|r| r.format)
+                .unwrap_or_default(),
+            Err(_) => OutputFormat::Raw,
+        }
+    }
+
+    fn intercept(&self, sample: Sample) -> Sample {
+        match self.format(&sample.key_expr) {
+            OutputFormat::Raw => sample,
+            format => {
+                self.converted.fetch_add(1, Ordering::Relaxed);
+                format.convert(sample)
+            }
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({
+            "converted": self.converted.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// The outgoing interceptor function, to be returned by the volume.
+pub(crate) fn outgoing_interceptor(
+    outgoing: &Arc<Outgoing>,
+) -> Arc<dyn Fn(Sample) -> Sample + Send + Sync> {
+    let outgoing = outgoing.clone();
+    Arc::new(move |sample| outgoing.intercept(sample))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> OwnedKeyExpr {
+        OwnedKeyExpr::from_str(s).unwrap()
+    }
+
+    fn payload(value: &Value) -> String {
+        String::from_utf8(value.payload.contiguous().into_owned()).unwrap()
+    }
+
+    fn rewrite(from: &str, to: &str) -> KeyRewrite {
+        KeyRewrite {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn key_rewrites() {
+        let r = rewrite("legacy/site1", "sites/1");
+        assert_eq!(r.apply("legacy/site1"), Some("sites/1".into()));
+        assert_eq!(r.apply("legacy/site1/temp"), Some("sites/1/temp".into()));
+        // the prefix must end at a chunk boundary
+        assert_eq!(r.apply("legacy/site10/temp"), None);
+        assert_eq!(r.apply("other/legacy/site1"), None);
+    }
+
+    #[test]
+    fn allowed_encodings() {
+        let t = IncomingTransforms {
+            allowed_encodings: Some(vec!["application/json".into(), "text/plain".into()]),
+            ..Default::default()
+        };
+        assert!(t.is_allowed(&KnownEncoding::AppJson.into()));
+        assert!(t.is_allowed(&Encoding::WithSuffix(
+            KnownEncoding::TextPlain,
+            ";charset=utf-8".into()
+        )));
+        // a suffix must be separated by ';'
+        assert!(!t.is_allowed(&Encoding::WithSuffix(KnownEncoding::TextPlain, "x".into())));
+        assert!(!t.is_allowed(&KnownEncoding::AppOctetStream.into()));
+        assert!(IncomingTransforms::default().is_allowed(&KnownEncoding::AppOctetStream.into()));
+    }
+
+    #[test]
+    fn json_projection() {
+        let t = IncomingTransforms {
+            json_fields: Some(vec!["a".into(), "c".into()]),
+            ..Default::default()
+        };
+        let value =
+            Value::from(r#"{"a":1,"b":2,"c":{"d":3}}"#).encoding(KnownEncoding::AppJson.into());
+        let projected: serde_json::Value =
+            serde_json::from_slice(&t.project(&value).unwrap()).unwrap();
+        assert_eq!(projected, json!({"a": 1, "c": {"d": 3}}));
+        // not a JSON object, or not a JSON encoding
+        let array = Value::from("[1,2]").encoding(KnownEncoding::AppJson.into());
+        assert_eq!(t.project(&array), None);
+        let text = Value::from(r#"{"a":1}"#).encoding(KnownEncoding::TextPlain.into());
+        assert_eq!(t.project(&text), None);
+    }
+
+    #[test]
+    fn dropped_marker() {
+        let incoming = Incoming::new(IncomingTransforms {
+            max_payload_size: Some(4),
+            ..Default::default()
+        });
+        let kept = incoming.intercept(Sample::new(key("a"), Value::from("1234")));
+        assert!(!incoming.is_dropped(&kept.value));
+        let dropped = incoming.intercept(Sample::new(key("a"), Value::from("12345")));
+        assert!(incoming.is_dropped(&dropped.value));
+        // the marker is specific to the volume, thus can't be forged
+        let other = Incoming::new(IncomingTransforms::default());
+        assert!(!other.is_dropped(&dropped.value));
+        let forged = Value::empty().encoding(Encoding::WithSuffix(
+            KnownEncoding::Empty,
+            DROPPED_ENCODING_PREFIX.into(),
+        ));
+        assert!(!incoming.is_dropped(&forged));
+    }
+
+    #[test]
+    fn output_conversions() {
+        let sample = Sample::new(key("a/b"), Value::from(vec![0xffu8, 0x00]));
+        let text = OutputFormat::Text.convert(sample);
+        assert_eq!(*text.value.encoding.prefix(), KnownEncoding::TextPlain);
+        assert_eq!(payload(&text.value), "/wA=");
+
+        let sample = Sample::new(
+            key("a/b"),
+            Value::from(r#"{"x":1}"#).encoding(KnownEncoding::AppJson.into()),
+        );
+        let json = OutputFormat::Json.convert(sample);
+        assert_eq!(*json.value.encoding.prefix(), KnownEncoding::AppJson);
+        let wrapped: serde_json::Value = serde_json::from_str(&payload(&json.value)).unwrap();
+        assert_eq!(wrapped["value"], json!({"x": 1}));
+        assert_eq!(wrapped["kind"], "PUT");
+    }
+
+    #[test]
+    fn outgoing_routes() {
+        let outgoing = Outgoing::default();
+        let json = outgoing.register(key("a/**"), OutputFormat::Json).unwrap();
+        // the storages with intersecting key expressions must have the same format
+        assert!(outgoing.register(key("a/b/*"), OutputFormat::Raw).is_err());
+        let _raw = outgoing.register(key("b/**"), OutputFormat::Raw).unwrap();
+        assert_eq!(outgoing.format(&key("a/x")), OutputFormat::Json);
+        assert_eq!(outgoing.format(&key("b/x")), OutputFormat::Raw);
+        assert_eq!(outgoing.format(&key("c")), OutputFormat::Raw);
+        assert!(!outgoing.is_empty());
+        // a route is removed with its storage
+        drop(json);
+        assert!(outgoing.is_empty());
+        assert!(outgoing.register(key("a/b/*"), OutputFormat::Raw).is_ok());
+    }
+}
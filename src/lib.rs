@@ -15,94 +15,145 @@
 use async_std::task;
 use async_trait::async_trait;
 use base64::{engine::general_purpose::STANDARD as b64_std_engine, Engine};
+use futures::future::join_all;
+use influxdb::integrations::serde_integration::DatabaseQueryResult;
 use influxdb::{
     Client, ReadQuery as InfluxRQuery, Timestamp as InfluxTimestamp, WriteQuery as InfluxWQuery,
 };
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use serde::Deserialize;
-use std::convert::{TryFrom, TryInto};
+use std::collections::HashMap;
+use std::convert::TryInto;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use uuid::Uuid;
 use zenoh::buffers::{SplitBuffer, ZBuf};
 use zenoh::prelude::*;
-use zenoh::properties::Properties;
 use zenoh::selector::TimeExpr;
 use zenoh::time::Timestamp;
 use zenoh::Result as ZResult;
-use zenoh_backend_traits::config::{
-    PrivacyGetResult, PrivacyTransparentGet, StorageConfig, VolumeConfig,
-};
+use zenoh_backend_traits::config::{StorageConfig, VolumeConfig};
 use zenoh_backend_traits::StorageInsertionResult;
 use zenoh_backend_traits::*;
 use zenoh_core::{bail, zerror};
 use zenoh_util::{Timed, TimedEvent, TimedHandle, Timer};
 
+mod compression;
+mod endpoints;
+mod health;
+mod influxql;
+mod ingest;
+mod interceptor;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mirror;
+mod retry;
+mod settings;
+mod setup;
+mod shard;
+mod stats;
+mod sweep;
+use endpoints::{ClientPool, Endpoints};
+use health::Health;
+use ingest::{
+    parse_number, Admission, ChangeFilter, ClockSkewGuard, DeadbandFilter, Digest, Quotas,
+    RateDecision, RateLimiter, QUARANTINE_MEASUREMENT,
+};
+use interceptor::{Incoming, Outgoing, OutgoingRoute};
+use mirror::{MirrorTarget, Mirrors};
+use retry::RetryPolicy;
+use settings::{
+    Collisions, HistoryMode, OnClosure, OnCollision, Privilege, StorageSettings, VolumeSettings,
+};
+use setup::{RemoteDb, StorageSetup};
+use shard::{Shard, Shards};
+use stats::{StorageStats, WriteOutcome};
+use sweep::{OrphanDbs, Registration, Sweep};
+
 // Properies used by the Backend
 pub const PROP_BACKEND_URL: &str = "url";
+pub const PROP_BACKEND_FAILOVER: &str = "failover";
 pub const PROP_BACKEND_USERNAME: &str = "username";
 pub const PROP_BACKEND_PASSWORD: &str = "password";
+pub const PROP_BACKEND_METRICS_PORT: &str = "metrics_port";
+pub const PROP_BACKEND_HEALTH_CHECK_INTERVAL: &str = "health_check_interval";
+pub const PROP_BACKEND_RETRY: &str = "retry";
+pub const PROP_BACKEND_CONNECT_TIMEOUT: &str = "connect_timeout";
+pub const PROP_BACKEND_REQUEST_TIMEOUT: &str = "request_timeout";
+pub const PROP_BACKEND_MAX_CONNECTIONS: &str = "max_connections_per_host";
+pub const PROP_BACKEND_KEEP_ALIVE: &str = "keep_alive";
+pub const PROP_BACKEND_COMPRESSION: &str = "compression";
+pub const PROP_BACKEND_INCOMING: &str = "incoming";
+pub const PROP_BACKEND_HISTORY: &str = "history";
+pub const PROP_BACKEND_ORPHAN_DBS: &str = "orphan_dbs";
+pub const PROP_BACKEND_ORPHAN_SWEEP_DELAY: &str = "orphan_sweep_delay";
+pub const PROP_BACKEND_ORPHAN_DBS_ALLOW: &str = "orphan_dbs_allow";
+pub const PROP_BACKEND_OWNER: &str = "owner";
 
 // Properies used by the Storage
 pub const PROP_STORAGE_DB: &str = "db";
 pub const PROP_STORAGE_CREATE_DB: &str = "create_db";
 pub const PROP_STORAGE_ON_CLOSURE: &str = "on_closure";
+pub const PROP_STORAGE_PRIVILEGE: &str = "privilege";
+pub const PROP_STORAGE_MIRRORS: &str = "mirrors";
+pub const PROP_STORAGE_MIRROR_ACK: &str = "mirror_ack";
+pub const PROP_STORAGE_SHARDS: &str = "shards";
+pub const PROP_STORAGE_SHARD_KEY_EXPR: &str = "key_expr";
+pub const PROP_STORAGE_OUTPUT_FORMAT: &str = "output_format";
+pub const PROP_STORAGE_HISTORY: &str = PROP_BACKEND_HISTORY;
+pub const PROP_STORAGE_COLLISIONS: &str = "collisions";
+pub const PROP_STORAGE_ON_COLLISION: &str = "on_collision";
+pub const PROP_STORAGE_MAX_FUTURE_DRIFT: &str = "max_future_drift";
+pub const PROP_STORAGE_ON_FUTURE_DRIFT: &str = "on_future_drift";
+pub const PROP_STORAGE_SKIP_UNCHANGED: &str = "skip_unchanged";
+pub const PROP_STORAGE_HEARTBEAT: &str = "heartbeat";
+pub const PROP_STORAGE_DEADBANDS: &str = "deadbands";
+pub const PROP_STORAGE_RATE_LIMITS: &str = "rate_limits";
+pub const PROP_STORAGE_QUOTAS: &str = "quotas";
+// Key expression of a deadband or rate limit rule
+pub const PROP_RULE_KEY_EXPR: &str = "key_expr";
+pub const PROP_STORAGE_NAMESPACE: &str = "namespace";
+pub const PROP_STORAGE_DB_NAMING: &str = "db_naming";
 pub const PROP_STORAGE_USERNAME: &str = PROP_BACKEND_USERNAME;
 pub const PROP_STORAGE_PASSWORD: &str = PROP_BACKEND_PASSWORD;
 
 // Special key for None (when the prefix being stripped exactly matches the key)
 pub const NONE_KEY: &str = "@@none_key@@";
+// Prefix of the databases names generated for the storages without `db`
+pub const GENERATED_DB_PREFIX: &str = "zenoh_db_";
+// Separator of the namespace and of the key in a measurement name (can't be in a key expression)
+pub const NAMESPACE_SEPARATOR: char = '#';
+
+// Selector parameter overriding the request timeout of a GET
+const TIMEOUT_PARAMETER: &str = "_timeout";
+// Selector parameter restricting a GET to the samples from a source (the ID of their timestamp)
+const SOURCE_PARAMETER: &str = "_source";
+
+// max number of times a point is shifted by 1ns to avoid a collision.
+// The points within this range are read at once, thus a PUT with collisions avoidance or resolution
+// costs a single extra query, whatever the number of shifts.
+const MAX_COLLISION_SHIFTS: u32 = 100;
+
+// number of measurements per query of get_all_entries()
+const ENTRIES_PAGE_SIZE: usize = 500;
 
 // delay after deletion to drop a measurement
 const DROP_MEASUREMENT_TIMEOUT_MS: u64 = 5000;
 
+// Measurement keeping the deletion timestamp of the dropped measurements (tagged with their name),
+// thus the deleted keys remain in get_all_entries().
+// As '#' is forbidden in key expressions, it never matches a key.
+const TOMBSTONES_MEASUREMENT: &str = "#tombstones";
+
 const GIT_VERSION: &str = git_version::git_version!(prefix = "v", cargo_prefix = "v");
 lazy_static::lazy_static!(
     static ref LONG_VERSION: String = format!("{} built with {}", GIT_VERSION, env!("RUSTC_VERSION"));
-    static ref INFLUX_REGEX_ALL: String = key_exprs_to_influx_regex(&["**".try_into().unwrap()]);
 );
 
 #[allow(dead_code)]
 const CREATE_BACKEND_TYPECHECK: CreateVolume = create_volume;
 
-fn get_private_conf<'a>(
-    config: &'a serde_json::Map<String, serde_json::Value>,
-    credit: &str,
-) -> ZResult<Option<&'a String>> {
-    match config.get_private(credit) {
-        PrivacyGetResult::NotFound => Ok(None),
-        PrivacyGetResult::Private(serde_json::Value::String(v)) => Ok(Some(v)),
-        PrivacyGetResult::Public(serde_json::Value::String(v)) => {
-            log::warn!(
-                r#"Value "{}" is given for `{}` publicly (i.e. is visible by anyone who can fetch the router configuration). You may want to replace `{}: "{}"` with `private: {{{}: "{}"}}`"#,
-                v,
-                credit,
-                credit,
-                v,
-                credit,
-                v
-            );
-            Ok(Some(v))
-        }
-        PrivacyGetResult::Both {
-            public: serde_json::Value::String(public),
-            private: serde_json::Value::String(private),
-        } => {
-            log::warn!(
-                r#"Value "{}" is given for `{}` publicly, but a private value also exists. The private value will be used, but the public value, which is {} the same as the private one, will still be visible in configurations."#,
-                public,
-                credit,
-                if public == private { "" } else { "not " }
-            );
-            Ok(Some(private))
-        }
-        _ => {
-            bail!("Optional property `{}` must be a string", credit)
-        }
-    }
-}
-
 #[no_mangle]
 pub fn create_volume(mut config: VolumeConfig) -> ZResult<Box<dyn Volume>> {
     // For some reasons env_logger is sometime not active in a loaded library.
@@ -110,76 +161,131 @@ pub fn create_volume(mut config: VolumeConfig) -> ZResult<Box<dyn Volume>> {
     let _ = env_logger::try_init();
     debug!("InfluxDB backend {}", LONG_VERSION.as_str());
 
+    let mut settings = VolumeSettings::parse(&config.rest)?;
     config
         .rest
         .insert("version".into(), LONG_VERSION.clone().into());
 
-    let url = match config.rest.get(PROP_BACKEND_URL) {
-        Some(serde_json::Value::String(url)) => url.clone(),
-        _ => {
-            bail!(
-                "Mandatory property `{}` for InfluxDb Backend must be a string",
-                PROP_BACKEND_URL
-            )
-        }
-    };
+    // The HTTP client shared by all the InfluxDB clients of this volume (and its connection pool)
+    let http_client = settings.http.http_client()?;
 
-    // The InfluxDB client used for administration purposes (show/create/drop databases)
-    let mut admin_client = Client::new(url, "");
+    // The InfluxDB endpoints, and the clients used for administration purposes (show/create/drop databases)
+    let endpoints = Arc::new(Endpoints::new(settings.urls.clone(), settings.failover));
+    let admin_clients =
+        ClientPool::new(&endpoints, "", &http_client, volume_credentials(&settings));
 
-    // Note: remove username/password from properties to not re-expose them in admin_status
-    let credentials = match (
-        get_private_conf(&config.rest, PROP_BACKEND_USERNAME)?,
-        get_private_conf(&config.rest, PROP_BACKEND_PASSWORD)?,
-    ) {
-        (Some(username), Some(password)) => {
-            admin_client = admin_client.with_auth(username, password);
-            Some((username.clone(), password.clone()))
-        }
-        (None, None) => None,
-        _ => {
-            bail!(
-                "Optional properties `{}` and `{}` must coexist",
-                PROP_BACKEND_USERNAME,
-                PROP_BACKEND_PASSWORD
-            )
-        }
+    // Monitor connectivity to InfluxDB in background: the volume starts even if InfluxDB is not reachable yet.
+    // Once reachable, check the credentials, trying to list databases
+    let health = health::start_probe(
+        admin_clients.clone(),
+        settings.health_check_interval,
+        settings.http.connect_timeout,
+        |client| {
+            let client = client.clone();
+            task::spawn(async move {
+                match show_databases(&client).await {
+                    Ok(dbs) => {
+                        // trick: if "_internal" db is not shown, it means the credentials are not for an admin
+                        if !dbs.iter().any(|e| e == "_internal") {
+                            warn!("The InfluxDB credentials are not for an admin user; the volume won't be able to create or drop any database")
+                        }
+                    }
+                    Err(e) => warn!("InfluxDb Volume failed to list databases : {}", e),
+                }
+            });
+        },
+    );
+
+    // Sweep the orphan databases in background, once the storages are created
+    let sweep = sweep::start_sweep(
+        settings.orphan_dbs,
+        settings.owner.clone(),
+        settings.orphan_dbs_allow.clone(),
+        admin_clients.clone(),
+        &health,
+        settings.orphan_sweep_delay,
+    );
+
+    // The transforms of the samples before they're stored, if any
+    let incoming = if settings.incoming.is_empty() {
+        None
+    } else {
+        Some(Arc::new(Incoming::new(std::mem::take(
+            &mut settings.incoming,
+        ))))
     };
 
-    // Check connectivity to InfluxDB, trying to list databases
-    match async_std::task::block_on(async { show_databases(&admin_client).await }) {
-        Ok(dbs) => {
-            // trick: if "_internal" db is not shown, it means the credentials are not for an admin
-            if !dbs.iter().any(|e| e == "_internal") {
-                warn!("The InfluxDB credentials are not for an admin user; the volume won't be able to create or drop any database")
-            }
-        }
-        Err(e) => bail!("Failed to create InfluxDb Volume : {}", e),
-    }
+    #[cfg(feature = "metrics")]
+    let metrics = Arc::new(metrics::MetricsRegistry::new(config.name.clone()));
+    #[cfg(feature = "metrics")]
+    let metrics_server = settings
+        .metrics_port
+        .map(|port| metrics::serve(&metrics, port));
 
     Ok(Box::new(InfluxDbBackend {
         admin_status: config,
-        admin_client,
-        credentials,
+        endpoints,
+        admin_clients,
+        http_client,
+        settings,
+        health,
+        sweep,
+        incoming,
+        outgoing: Arc::new(Outgoing::default()),
+        #[cfg(feature = "metrics")]
+        metrics,
+        #[cfg(feature = "metrics")]
+        _metrics_server: metrics_server,
     }))
 }
 
 pub struct InfluxDbBackend {
     admin_status: VolumeConfig,
-    admin_client: Client,
-    credentials: Option<(String, String)>,
+    endpoints: Arc<Endpoints>,
+    admin_clients: ClientPool,
+    http_client: surf::Client,
+    settings: VolumeSettings,
+    health: Arc<Health>,
+    sweep: Arc<Sweep>,
+    incoming: Option<Arc<Incoming>>,
+    outgoing: Arc<Outgoing>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<metrics::MetricsRegistry>,
+    // the HTTP server of the metrics, if any, stopped when the volume is dropped
+    #[cfg(feature = "metrics")]
+    _metrics_server: Option<metrics::MetricsServer>,
 }
 
 #[async_trait]
 impl Volume for InfluxDbBackend {
     fn get_admin_status(&self) -> serde_json::Value {
-        self.admin_status.to_json_value()
+        let mut status = self.admin_status.to_json_value();
+        if let Some(obj) = status.as_object_mut() {
+            obj.insert("settings".into(), json_or_null(&self.settings));
+            obj.insert("health".into(), self.health.to_json());
+            obj.insert("endpoints".into(), self.endpoints.to_json());
+            if self.settings.orphan_dbs != OrphanDbs::Off {
+                obj.insert("orphan_dbs".into(), self.sweep.to_json());
+            }
+            if let Some(incoming) = &self.incoming {
+                obj.insert("incoming".into(), incoming.to_json());
+            }
+            if !self.outgoing.is_empty() {
+                obj.insert("outgoing".into(), self.outgoing.to_json());
+            }
+            #[cfg(feature = "metrics")]
+            obj.insert("metrics".into(), self.metrics.render().into());
+        }
+        status
     }
 
     fn get_capability(&self) -> Capability {
         Capability {
             persistence: Persistence::Durable,
-            history: History::All,
+            history: match self.settings.history {
+                HistoryMode::All => History::All,
+                HistoryMode::Latest => History::Latest,
+            },
             read_cost: 1,
         }
     }
@@ -189,64 +295,27 @@ impl Volume for InfluxDbBackend {
             Some(v) => v,
             None => bail!("influxdb backed storages need some volume-specific configuration"),
         };
-        let on_closure = match volume_cfg.get(PROP_STORAGE_ON_CLOSURE) {
-            Some(serde_json::Value::String(x)) if x == "drop_series" => OnClosure::DropSeries,
-            Some(serde_json::Value::String(x)) if x == "drop_db" => OnClosure::DropDb,
-            Some(serde_json::Value::String(x)) if x == "do_nothing" => OnClosure::DoNothing,
-            None => OnClosure::DoNothing,
-            Some(_) => {
-                bail!(
-                    r#"`{}` property of storage `{}` must be one of "do_nothing" (default), "drop_db" and "drop_series""#,
-                    PROP_STORAGE_ON_CLOSURE,
-                    &config.name
-                )
-            }
-        };
-        let (db, createdb) = match volume_cfg.get(PROP_STORAGE_DB) {
-            Some(serde_json::Value::String(s)) => (
-                s.clone(),
-                match volume_cfg.get(PROP_STORAGE_CREATE_DB) {
-                    None | Some(serde_json::Value::Bool(false)) => false,
-                    Some(serde_json::Value::Bool(true)) => true,
-                    Some(_) => todo!(),
-                },
-            ),
-            None => (generate_db_name(), true),
-            _ => bail!(""),
-        };
+        let settings = StorageSettings::parse(
+            &config.name,
+            &config.key_expr,
+            volume_cfg,
+            self.settings.history,
+        )?;
+        // not swept while the storage exists (nor while it's created)
+        let registration = self.sweep.register(&settings.db);
+        // The conversion of the replies (failing if conflicting with another storage's)
+        let output_route = self
+            .outgoing
+            .register(config.key_expr.clone(), settings.output_format)?;
+        let db = settings.db.clone();
 
-        // The Influx client on database used to write/query on this storage
-        // (using the same URL than backend's admin_client, but with storage credentials)
-        let mut client = Client::new(self.admin_client.database_url(), &db);
-
-        // Use credentials if specified in storage's volume config
-        let storage_username = match (
-            get_private_conf(volume_cfg, PROP_STORAGE_USERNAME)?,
-            get_private_conf(volume_cfg, PROP_STORAGE_PASSWORD)?,
-        ) {
-            (Some(username), Some(password)) => {
-                client = client.with_auth(username, password);
-                Some(username.clone())
-            }
-            (None, None) => None,
-            _ => {
-                bail!(
-                    "Optional properties `{}` and `{}` must coexist",
-                    PROP_STORAGE_USERNAME,
-                    PROP_STORAGE_PASSWORD
-                )
-            }
+        // The Influx clients on database used to write/query on this storage
+        // (using the same endpoints than backend's admin_clients, but with storage credentials)
+        let credentials = match (&settings.username, &settings.password) {
+            (Some(username), Some(password)) => Some((username.as_str(), password.as_str())),
+            _ => None,
         };
-
-        // Check if the database exists (using storages credentials)
-        if !is_db_existing(&client, &db).await? {
-            if createdb {
-                // create db using backend's credentials
-                create_db(&self.admin_client, &db, storage_username).await?;
-            } else {
-                bail!("Database '{}' doesn't exist in InfluxDb", db)
-            }
-        }
+        let client = ClientPool::new(&self.endpoints, &db, &self.http_client, credentials);
 
         // re-insert the actual name of database (in case it has been generated)
         config
@@ -257,72 +326,303 @@ impl Volume for InfluxDbBackend {
             .or_insert(db.clone().into());
 
         // The Influx client on database with backend's credentials (admin), to drop measurements and database
-        let mut admin_client = Client::new(self.admin_client.database_url(), db);
-        if let Some((username, password)) = &self.credentials {
-            admin_client = admin_client.with_auth(username, password);
+        let admin_client = ClientPool::new(
+            &self.endpoints,
+            &db,
+            &self.http_client,
+            volume_credentials(&self.settings),
+        );
+
+        // The Influx clients on the mirrors, if any (with the mirrors credentials)
+        let mut remote_dbs = Vec::with_capacity(settings.mirrors.len() + settings.shards.len());
+        let mut mirrors = Vec::with_capacity(settings.mirrors.len());
+        for mirror in &settings.mirrors {
+            let mut client =
+                Client::new(&mirror.url, &mirror.db).with_http_client(self.http_client.clone());
+            if let Some((username, password)) = &mirror.credentials {
+                client = client.with_auth(username, password);
+            }
+            remote_dbs.push(RemoteDb {
+                name: format!("Mirror {}", mirror.url),
+                db: mirror.db.clone(),
+                client: client.clone(),
+            });
+            mirrors.push(MirrorTarget {
+                url: mirror.url.clone(),
+                client,
+            });
         }
+        let mirrors = Mirrors::new(settings.mirror_ack, mirrors);
 
-        Ok(Box::new(InfluxDbStorage {
+        // The Influx clients on the shards, if any (with the shards credentials)
+        let mut shards = Vec::with_capacity(settings.shards.len());
+        for shard in &settings.shards {
+            if !shard.key_expr.intersects(&config.key_expr) {
+                bail!(
+                    "Shard {} of storage `{}` can't store any key: its key expression {} doesn't intersect {}",
+                    shard.remote.url,
+                    config.name,
+                    shard.key_expr,
+                    config.key_expr
+                )
+            }
+            let mut client = Client::new(&shard.remote.url, &shard.remote.db)
+                .with_http_client(self.http_client.clone());
+            if let Some((username, password)) = &shard.remote.credentials {
+                client = client.with_auth(username, password);
+            }
+            remote_dbs.push(RemoteDb {
+                name: format!("Shard {}", shard.remote.url),
+                db: shard.remote.db.clone(),
+                client: client.clone(),
+            });
+            shards.push(Shard {
+                key_expr: shard.key_expr.clone(),
+                url: shard.remote.url.clone(),
+                client,
+            });
+        }
+        let shards = Shards::new(shards, config.strip_prefix.clone());
+
+        // The setup of the storage in InfluxDB: run now if InfluxDB is reachable (failing the storage creation),
+        // or else deferred until it is, the storage rejecting all operations meanwhile
+        let owner = self.settings.owner.clone().filter(|_| settings.random_db);
+        let setup = Arc::new(StorageSetup::new(
+            &config.name,
+            &settings,
+            owner,
+            self.admin_clients.clone(),
+            admin_client.clone(),
+            remote_dbs,
+        ));
+        let deferred = !self.health.is_connected();
+        if deferred {
+            info!(
+                "InfluxDB is not reachable; setup of storage `{}` is deferred until it is",
+                config.name
+            );
+            setup::defer(&setup, &self.health);
+        } else {
+            setup.run().await?;
+        }
+
+        let clock_skew = settings
+            .max_future_drift
+            .map(|max_drift| ClockSkewGuard::new(max_drift, settings.on_future_drift));
+        let change_filter = settings
+            .skip_unchanged
+            .then(|| ChangeFilter::new(settings.heartbeat));
+        let deadband = (!settings.deadbands.is_empty())
+            .then(|| DeadbandFilter::new(settings.deadbands.clone()));
+        let rate_limiter = (!settings.rate_limits.is_empty())
+            .then(|| RateLimiter::new(settings.rate_limits.clone()));
+        let measurement_prefix = match &settings.namespace {
+            Some(namespace) => format!("{namespace}{NAMESPACE_SEPARATOR}"),
+            None => String::new(),
+        };
+        let quotas = settings
+            .quotas
+            .clone()
+            .map(|quotas| Quotas::new(&config.name, quotas));
+
+        let stats = Arc::new(StorageStats::default());
+        #[cfg(feature = "metrics")]
+        self.metrics.register(&config.name, &stats);
+
+        let storage = InfluxDbStorage {
             config,
+            settings,
             admin_client,
             client,
-            on_closure,
+            mirrors,
+            shards,
+            incoming: self.incoming.clone(),
+            _output_route: output_route,
+            clock_skew,
+            change_filter,
+            deadband,
+            rate_limiter,
+            quotas,
+            measurement_prefix,
             timer: Timer::default(),
-        }))
+            retry: self.settings.retry.clone(),
+            stats,
+            setup,
+            _registration: registration,
+        };
+        // once set up later, the last values of the keys and their number are read on demand
+        if deferred {
+            return Ok(Box::new(storage));
+        }
+
+        // the last values of the keys, for `skip_unchanged`
+        if let Some(filter) = &storage.change_filter {
+            if let Err(e) = storage.seed_change_filter(filter).await {
+                warn!(
+                    "Failed to read the last values of storage {} (read on the 1st PUT of each key instead) : {}",
+                    storage.config.name, e
+                );
+            }
+        }
+
+        // the initial usage of the keys quota
+        if let Some(quotas) = storage.quotas.as_ref().filter(|q| q.limits_keys()) {
+            match storage.count_keys().await {
+                Ok(count) => quotas.set_key_count(count),
+                Err(e) => warn!(
+                    "Failed to count the keys of storage {} : {}",
+                    storage.config.name, e
+                ),
+            }
+        }
+        Ok(Box::new(storage))
     }
 
     fn incoming_data_interceptor(&self) -> Option<Arc<dyn Fn(Sample) -> Sample + Send + Sync>> {
-        None
+        self.incoming
+            .as_ref()
+            .map(interceptor::incoming_interceptor)
     }
 
     fn outgoing_data_interceptor(&self) -> Option<Arc<dyn Fn(Sample) -> Sample + Send + Sync>> {
-        None
+        // Note: called before the storages creation, thus always returned
+        // (the replies of the storages without `output_format` are returned as is)
+        Some(interceptor::outgoing_interceptor(&self.outgoing))
     }
 }
 
-enum OnClosure {
-    DropDb,
-    DropSeries,
-    DoNothing,
+struct InfluxDbStorage {
+    config: StorageConfig,
+    settings: StorageSettings,
+    admin_client: ClientPool,
+    client: ClientPool,
+    mirrors: Mirrors,
+    shards: Shards,
+    // the incoming interceptor of the volume, marking the samples it drops
+    incoming: Option<Arc<Incoming>>,
+    // the output format of the replies, registered in the outgoing interceptor while the storage lives
+    _output_route: Arc<OutgoingRoute>,
+    clock_skew: Option<ClockSkewGuard>,
+    change_filter: Option<ChangeFilter>,
+    deadband: Option<DeadbandFilter>,
+    rate_limiter: Option<RateLimiter>,
+    quotas: Option<Quotas>,
+    // the prefix of the measurements names, for the storage's namespace (empty if none)
+    measurement_prefix: String,
+    timer: Timer,
+    retry: RetryPolicy,
+    stats: Arc<StorageStats>,
+    // the setup of the storage in InfluxDB, possibly deferred
+    setup: Arc<StorageSetup>,
+    // the database's registration in the volume's sweep, undone when the storage is dropped
+    _registration: Registration,
 }
 
-impl TryFrom<&Properties> for OnClosure {
-    type Error = zenoh_core::Error;
-    fn try_from(p: &Properties) -> ZResult<OnClosure> {
-        match p.get(PROP_STORAGE_ON_CLOSURE) {
-            Some(s) => {
-                if s == "drop_db" {
-                    Ok(OnClosure::DropDb)
-                } else if s == "drop_series" {
-                    Ok(OnClosure::DropSeries)
-                } else {
-                    bail!("Unsupported value for 'on_closure' property: {}", s)
+impl InfluxDbStorage {
+    // Write on a shard, or on the storage's database and on its mirrors (if any) according to the acknowledgement policy
+    async fn write<Q: influxdb::Query + Sync>(
+        &self,
+        shard: Option<&Shard>,
+        query: &Q,
+    ) -> Result<(), influxdb::Error> {
+        if let Some(shard) = shard {
+            return retry::with_backoff(
+                &self.retry,
+                &self.stats,
+                &self.stats.write_latency,
+                || shard.client.query(query),
+            )
+            .await
+            .map(|_| ());
+        }
+        let primary =
+            retry::with_backoff(&self.retry, &self.stats, &self.stats.write_latency, || {
+                self.client.run(false, |c| c.query(query))
+            });
+        if self.mirrors.is_empty() {
+            return primary.await.map(|_| ());
+        }
+        let mirrors = join_all(self.mirrors.mirrors().iter().map(|mirror| {
+            retry::with_backoff(
+                &self.retry,
+                &self.stats,
+                &self.stats.write_latency,
+                move || mirror.client.query(query),
+            )
+        }));
+        let (primary, mirrors) = futures::join!(primary, mirrors);
+        let results = std::iter::once(primary)
+            .chain(mirrors)
+            .map(|r| r.map(|_| ()))
+            .collect();
+        self.mirrors.acknowledge(results)
+    }
+
+    // Query a shard, or the storage's database (or the first healthy mirror if it failed)
+    async fn read(
+        &self,
+        shard: Option<&Shard>,
+        query: &InfluxRQuery,
+    ) -> Result<DatabaseQueryResult, influxdb::Error> {
+        if let Some(shard) = shard {
+            return retry::with_backoff(
+                &self.retry,
+                &self.stats,
+                &self.stats.query_latency,
+                || shard.client.json_query(query.clone()),
+            )
+            .await;
+        }
+        let order = self.mirrors.read_order();
+        let mut i = 0;
+        loop {
+            let target = order[i];
+            let result = if target == 0 {
+                retry::with_backoff(&self.retry, &self.stats, &self.stats.query_latency, || {
+                    self.client.run(true, |c| c.json_query(query.clone()))
+                })
+                .await
+            } else {
+                let mirror = &self.mirrors.mirrors()[target - 1];
+                retry::with_backoff(&self.retry, &self.stats, &self.stats.query_latency, || {
+                    mirror.client.json_query(query.clone())
+                })
+                .await
+            };
+            match result {
+                Ok(result) => {
+                    self.mirrors.stats(target).on_success();
+                    return Ok(result);
+                }
+                Err(e) if retry::is_transient(&e) => {
+                    self.mirrors.stats(target).on_failure(&e);
+                    if i + 1 == order.len() {
+                        return Err(e);
+                    }
+                    debug!(
+                        "Query failed on target #{}, try the next one: {}",
+                        target, e
+                    );
+                    i += 1;
                 }
+                Err(e) => return Err(e),
             }
-            None => Ok(OnClosure::DoNothing),
         }
     }
-}
 
-struct InfluxDbStorage {
-    config: StorageConfig,
-    admin_client: Client,
-    client: Client,
-    on_closure: OnClosure,
-    timer: Timer,
-}
-
-impl InfluxDbStorage {
-    async fn get_deletion_timestamp(&self, measurement: &str) -> ZResult<Option<Timestamp>> {
+    async fn get_deletion_timestamp(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+    ) -> ZResult<Option<Timestamp>> {
         #[derive(Deserialize, Debug, PartialEq)]
         struct QueryResult {
             timestamp: String,
         }
 
-        let query = InfluxRQuery::new(format!(
-            r#"SELECT "timestamp" FROM "{measurement}" WHERE kind='DEL' ORDER BY time DESC LIMIT 1"#
-        ));
-        match self.client.json_query(query).await {
+        let query = InfluxRQuery::new(influxql::select_deletion_timestamp(measurement));
+        let result = self.read(shard, &query).await;
+        match result {
             Ok(mut result) => match result.deserialize_next::<QueryResult>() {
                 Ok(qr) => {
                     if !qr.series.is_empty() && !qr.series[0].values.is_empty() {
@@ -353,20 +653,424 @@ impl InfluxDbStorage {
         }
     }
 
-    async fn schedule_measurement_drop(&self, measurement: &str) -> TimedHandle {
+    // The zenoh timestamp of the points from a source within a time range [from, to) of the measurement,
+    // indexed by their time
+    async fn get_timestamps_between(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+        from: u128,
+        to: u128,
+        source: &str,
+    ) -> ZResult<HashMap<u128, Timestamp>> {
+        #[derive(Deserialize, Debug)]
+        struct QueryResult {
+            time: String,
+            timestamp: String,
+        }
+
+        let query = InfluxRQuery::new(influxql::select_puts_between(measurement, from, to, source));
+        let result = match self.read(shard, &query).await {
+            Ok(mut result) => result.deserialize_next::<QueryResult>(),
+            Err(err) => bail!(
+                "Failed to get points at time {} of measurement {} : {}",
+                from,
+                measurement,
+                err
+            ),
+        };
+        let points = match result {
+            Ok(qr) => qr.series.into_iter().flat_map(|s| s.values),
+            Err(err) => bail!(
+                "Failed to get points at time {} of measurement {} : {}",
+                from,
+                measurement,
+                err
+            ),
+        };
+        let mut timestamps = HashMap::new();
+        for point in points {
+            // the time is returned in RFC3339 format, with nanoseconds
+            let time = humantime::parse_rfc3339(&point.time)
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .ok_or_else(|| {
+                    zerror!(
+                        "Failed to parse time {} of point of measurement {}",
+                        point.time,
+                        measurement
+                    )
+                })?;
+            let timestamp = point.timestamp.parse::<Timestamp>().map_err(|err| {
+                zerror!(
+                    "Failed to parse timestamp of point at time {} of measurement {} : {}",
+                    point.time,
+                    measurement,
+                    err.cause
+                )
+            })?;
+            timestamps.insert(time.as_nanos(), timestamp);
+        }
+        Ok(timestamps)
+    }
+
+    // The time at which a sample can be written, according to the collisions avoidance and resolution policies,
+    // or None if the sample must be ignored
+    async fn collision_free_time(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+        mut influx_time: u128,
+        timestamp: &Timestamp,
+    ) -> ZResult<Option<u128>> {
+        let settings = &self.settings;
+        if settings.collisions == Collisions::None
+            && settings.on_collision == OnCollision::Overwrite
+        {
+            return Ok(Some(influx_time));
+        }
+        // the source being a tag, samples from different sources never collide
+        let source = timestamp.get_id().to_string();
+        // read at once the points at all the times the sample may be shifted to
+        let shifts = match settings.collisions {
+            Collisions::UniqueTime => MAX_COLLISION_SHIFTS,
+            _ => 1,
+        };
+        let stored = self
+            .get_timestamps_between(
+                shard,
+                measurement,
+                influx_time,
+                influx_time + shifts as u128,
+                &source,
+            )
+            .await?;
+        for _ in 0..shifts {
+            let existing = match stored.get(&influx_time) {
+                // the same sample (e.g. re-published) overwrites itself
+                Some(existing) if existing != timestamp => *existing,
+                _ => return Ok(Some(influx_time)),
+            };
+            StorageStats::incr(&self.stats.collisions);
+            if settings.collisions == Collisions::UniqueTime {
+                influx_time += 1;
+                continue;
+            }
+            debug!(
+                "Sample for {:?} with timestamp {} collides with stored sample with timestamp {}",
+                measurement, timestamp, existing
+            );
+            return Ok(match settings.on_collision {
+                OnCollision::Overwrite => Some(influx_time),
+                OnCollision::KeepExisting => None,
+                OnCollision::KeepGreatest if existing > *timestamp => None,
+                OnCollision::KeepGreatest => Some(influx_time),
+            });
+        }
+        bail!(
+            "Failed to find a collision-free time for sample on {:?} with timestamp {}",
+            measurement,
+            timestamp
+        )
+    }
+
+    // The digest and time of the latest value of the measurement (see ChangeFilter),
+    // or None if it has no value or if it's deleted
+    async fn get_last_value(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+    ) -> ZResult<Option<(Digest, u128)>> {
+        let query = InfluxRQuery::new(influxql::select_last_point(measurement));
+        let result = match self.read(shard, &query).await {
+            Ok(mut result) => result.deserialize_next::<LastPoint>(),
+            Err(err) => bail!(
+                "Failed to get latest point of measurement {} : {}",
+                measurement,
+                err
+            ),
+        };
+        match result {
+            Ok(mut qr) => match qr.series.pop().and_then(|mut s| s.values.pop()) {
+                Some(point) => last_value(measurement, point),
+                None => Ok(None),
+            },
+            Err(err) => bail!(
+                "Failed to get latest point of measurement {} : {}",
+                measurement,
+                err
+            ),
+        }
+    }
+
+    // Seed the ChangeFilter with the last value of the keys of the storage,
+    // paging through the measurements
+    async fn seed_change_filter(&self, filter: &ChangeFilter) -> ZResult<()> {
+        for shard in self.shards.everywhere() {
+            let mut pager = MeasurementsPager::new(Listing::Keys);
+            loop {
+                // the keys beyond the filter's capacity are read on their 1st PUT
+                if filter.is_full() {
+                    return Ok(());
+                }
+                let measurements = self.next_measurements(shard, &mut pager).await?;
+                if measurements.is_empty() {
+                    break;
+                }
+                let influx_query_str = influxql::select_last_points(&measurements);
+                let influx_query = InfluxRQuery::new(&influx_query_str);
+                debug!(
+                    "Get latest points of {} measurements with Influx query: {}",
+                    measurements.len(),
+                    influx_query_str
+                );
+                let retn = match self.read(shard, &influx_query).await {
+                    Ok(mut query_result) => query_result.deserialize_next::<LastPoint>(),
+                    Err(e) => bail!(
+                        "Failed to query InfluxDb with '{}' : {}",
+                        influx_query_str,
+                        e
+                    ),
+                };
+                let retn = match retn {
+                    Ok(retn) => retn,
+                    Err(e) => bail!(
+                        "Failed to parse result of InfluxDB query '{}': {}",
+                        influx_query_str,
+                        e
+                    ),
+                };
+                // one serie per measurement
+                for mut serie in retn.series {
+                    if is_internal_measurement(&serie.name) {
+                        continue;
+                    }
+                    if let Some(point) = serie.values.pop() {
+                        match last_value(&serie.name, point) {
+                            Ok(value) => filter.seed(&serie.name, value),
+                            Err(e) => warn!("{}", e),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns true if a PUT on the measurement is within the storage's quotas
+    async fn check_quotas(
+        &self,
+        quotas: &Quotas,
+        shard: Option<&Shard>,
+        measurement: &str,
+        value: &Value,
+    ) -> ZResult<bool> {
+        if !quotas.check_payload(measurement, value.payload.len()) {
+            return Ok(false);
+        }
+        if !quotas.counts_points() {
+            return Ok(true);
+        }
+        let points = self.known_points(quotas, shard, measurement).await?;
+        let key_count =
+            if points == 0 && quotas.limits_keys() && !quotas.is_quarantined(measurement) {
+                Some(self.known_key_count(quotas).await?)
+            } else {
+                None
+            };
+        Ok(quotas.check_put(measurement, points, key_count))
+    }
+
+    // Enforce the quotas of the storage on a quarantined sample, not adding points to its key:
+    // its payload size, and the number of keys if its key has no points nor quarantined samples yet
+    async fn check_quarantine_quotas(
+        &self,
+        quotas: &Quotas,
+        shard: Option<&Shard>,
+        measurement: &str,
+        value: &Value,
+    ) -> ZResult<bool> {
+        if !quotas.check_payload(measurement, value.payload.len()) {
+            return Ok(false);
+        }
+        if !quotas.limits_keys()
+            || quotas.is_quarantined(measurement)
+            || self.known_points(quotas, shard, measurement).await? > 0
+        {
+            return Ok(true);
+        }
+        let key_count = self.known_key_count(quotas).await?;
+        Ok(quotas.check_new_key(measurement, Some(key_count)))
+    }
+
+    // The number of points of the measurement, counted in InfluxDB if not known by the quotas
+    async fn known_points(
+        &self,
+        quotas: &Quotas,
+        shard: Option<&Shard>,
+        measurement: &str,
+    ) -> ZResult<u64> {
+        match quotas.points(measurement) {
+            Some(points) => Ok(points),
+            None => {
+                let points = self.count_points(shard, measurement).await?;
+                quotas.set_points(measurement, points);
+                Ok(points)
+            }
+        }
+    }
+
+    // The number of keys, counted in InfluxDB if not known by the quotas
+    async fn known_key_count(&self, quotas: &Quotas) -> ZResult<u64> {
+        match quotas.key_count() {
+            Some(count) => Ok(count),
+            None => {
+                let count = self.count_keys().await?;
+                quotas.set_key_count(count);
+                Ok(count)
+            }
+        }
+    }
+
+    // The number of points of the measurement (0 if it doesn't exist)
+    async fn count_points(&self, shard: Option<&Shard>, measurement: &str) -> ZResult<u64> {
+        #[derive(Deserialize, Debug)]
+        struct QueryResult {
+            count: u64,
+        }
+
+        let query = InfluxRQuery::new(influxql::count_points(measurement));
+        match self.read(shard, &query).await {
+            Ok(mut result) => match result.deserialize_next::<QueryResult>() {
+                Ok(qr) => Ok(qr
+                    .series
+                    .first()
+                    .and_then(|s| s.values.first())
+                    .map_or(0, |r| r.count)),
+                Err(err) => bail!(
+                    "Failed to count the points of measurement {} : {}",
+                    measurement,
+                    err
+                ),
+            },
+            Err(err) => bail!(
+                "Failed to count the points of measurement {} : {}",
+                measurement,
+                err
+            ),
+        }
+    }
+
+    // The number of keys (measurements, and keys with quarantined samples) in the storage's database and in its shards
+    async fn count_keys(&self) -> ZResult<u64> {
+        #[derive(Deserialize, Debug)]
+        struct QueryResult {
+            count: u64,
+        }
+
+        // the keys with quarantined samples are counted, as they add series to the quarantine
+        let queries = [
+            influxql::show_measurement_cardinality(&self.measurement_prefix),
+            influxql::show_quarantined_keys_cardinality(
+                QUARANTINE_MEASUREMENT,
+                &self.measurement_prefix,
+            ),
+        ];
+        let mut count = 0;
+        for shard in self.shards.everywhere() {
+            for query in &queries {
+                let query = InfluxRQuery::new(query);
+                match self.read(shard, &query).await {
+                    Ok(mut result) => match result.deserialize_next::<QueryResult>() {
+                        Ok(qr) => {
+                            count += qr
+                                .series
+                                .iter()
+                                .flat_map(|s| s.values.iter())
+                                .map(|r| r.count)
+                                .sum::<u64>()
+                        }
+                        Err(err) => bail!("Failed to count the keys : {}", err),
+                    },
+                    Err(err) => bail!("Failed to count the keys : {}", err),
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    // Returns true if the measurement has a point more recent than a time (other than a deletion marker)
+    async fn has_put_after(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+        influx_time: u128,
+    ) -> ZResult<bool> {
+        #[derive(Deserialize, Debug)]
+        struct QueryResult {
+            #[allow(dead_code)]
+            timestamp: String,
+        }
+
+        let query = InfluxRQuery::new(influxql::select_put_after(measurement, influx_time));
+        match self.read(shard, &query).await {
+            Ok(mut result) => match result.deserialize_next::<QueryResult>() {
+                Ok(qr) => Ok(!qr.series.is_empty() && !qr.series[0].values.is_empty()),
+                Err(err) => bail!(
+                    "Failed to get latest point of measurement {} : {}",
+                    measurement,
+                    err
+                ),
+            },
+            Err(err) => bail!(
+                "Failed to get latest point of measurement {} : {}",
+                measurement,
+                err
+            ),
+        }
+    }
+
+    async fn schedule_measurement_drop(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+        timestamp: Timestamp,
+    ) -> TimedHandle {
+        // the measurement is dropped from the shard, or from the storage's database and its mirrors
+        let clients = match shard {
+            Some(shard) => vec![shard.client.clone()],
+            None => std::iter::once(self.admin_client.active().clone())
+                .chain(self.mirrors.mirrors().iter().map(|m| m.client.clone()))
+                .collect(),
+        };
         let event = TimedEvent::once(
             Instant::now() + Duration::from_millis(DROP_MEASUREMENT_TIMEOUT_MS),
             TimedMeasurementDrop {
-                client: self.admin_client.clone(),
+                clients,
                 measurement: measurement.to_string(),
+                deletion: timestamp,
+                stats: self.stats.clone(),
             },
         );
         let handle = event.get_handle();
         self.timer.add_async(event).await;
+        StorageStats::incr(&self.stats.pending_drops);
         handle
     }
 
+    // The measurement storing a key, in the storage's namespace
+    fn measurement(&self, key: Option<OwnedKeyExpr>) -> String {
+        match key {
+            Some(key) => format!("{}{}", self.measurement_prefix, key),
+            None => format!("{}{}", self.measurement_prefix, NONE_KEY),
+        }
+    }
+
     fn keyexpr_from_serie(&self, serie_name: &str) -> ZResult<Option<OwnedKeyExpr>> {
+        let serie_name = match serie_name.strip_prefix(self.measurement_prefix.as_str()) {
+            Some(name) => name,
+            None => bail!("Serie {} is not in the storage's namespace", serie_name),
+        };
         if serie_name.eq(NONE_KEY) {
             Ok(None)
         } else {
@@ -381,8 +1085,34 @@ impl InfluxDbStorage {
 #[async_trait]
 impl Storage for InfluxDbStorage {
     fn get_admin_status(&self) -> serde_json::Value {
-        // TODO: possibly add more properties in returned Value for more information about this storage
-        self.config.to_json_value()
+        let mut status = self.config.to_json_value();
+        if let Some(obj) = status.as_object_mut() {
+            obj.insert("settings".into(), json_or_null(&self.settings));
+            obj.insert("stats".into(), self.stats.to_json());
+            obj.insert("setup".into(), self.setup.to_json());
+            if let Some(clock_skew) = &self.clock_skew {
+                obj.insert("clock_skew".into(), clock_skew.to_json());
+            }
+            if let Some(change_filter) = &self.change_filter {
+                obj.insert("skip_unchanged".into(), change_filter.to_json());
+            }
+            if let Some(deadband) = &self.deadband {
+                obj.insert("deadband".into(), deadband.to_json());
+            }
+            if let Some(rate_limiter) = &self.rate_limiter {
+                obj.insert("rate_limit".into(), rate_limiter.to_json());
+            }
+            if let Some(quotas) = &self.quotas {
+                obj.insert("quotas".into(), quotas.to_json());
+            }
+            if !self.mirrors.is_empty() {
+                obj.insert(
+                    "mirrors".into(),
+                    self.mirrors.to_json(self.client.active().database_url()),
+                );
+            }
+        }
+        status
     }
 
     async fn put(
@@ -391,72 +1121,393 @@ impl Storage for InfluxDbStorage {
         value: Value,
         timestamp: Timestamp,
     ) -> ZResult<StorageInsertionResult> {
-        let measurement = key.unwrap_or_else(|| OwnedKeyExpr::from_str(NONE_KEY).unwrap());
+        if self
+            .incoming
+            .as_ref()
+            .map_or(false, |i| i.is_dropped(&value))
+        {
+            StorageStats::incr(&self.stats.dropped);
+            return Ok(StorageInsertionResult::Outdated);
+        }
+        let result =
+            retry::with_deadline(self.retry.deadline, self.put_value(key, value, timestamp)).await;
+        self.stats.record_outcome(&self.stats.puts, result)
+    }
+
+    async fn delete(
+        &mut self,
+        key: Option<OwnedKeyExpr>,
+        timestamp: Timestamp,
+    ) -> ZResult<StorageInsertionResult> {
+        let result =
+            retry::with_deadline(self.retry.deadline, self.delete_key(key, timestamp)).await;
+        self.stats.record_outcome(&self.stats.deletes, result)
+    }
+
+    async fn get(
+        &mut self,
+        key: Option<OwnedKeyExpr>,
+        parameters: &str,
+    ) -> ZResult<Vec<StoredData>> {
+        StorageStats::incr(&self.stats.gets);
+        let result = match timeout_from_parameters(parameters) {
+            Ok(Some(timeout)) => {
+                match async_std::future::timeout(timeout, self.get_values(key, parameters)).await {
+                    Ok(result) => result,
+                    Err(_) => Err(zerror!(
+                        "Query on InfluxDb storage {} timed out after {:?}",
+                        self.config.name,
+                        timeout
+                    )
+                    .into()),
+                }
+            }
+            Ok(None) => {
+                retry::with_deadline(self.retry.deadline, self.get_values(key, parameters)).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            self.stats.record_error(e);
+        }
+        result
+    }
+
+    async fn get_all_entries(&self) -> ZResult<Vec<(Option<OwnedKeyExpr>, Timestamp)>> {
+        let result = self.get_entries().await;
+        if let Err(e) = &result {
+            self.stats.record_error(e);
+        }
+        result
+    }
+}
+
+impl InfluxDbStorage {
+    async fn put_value(
+        &mut self,
+        key: Option<OwnedKeyExpr>,
+        value: Value,
+        timestamp: Timestamp,
+    ) -> ZResult<WriteOutcome> {
+        self.setup.check_ready()?;
+        let shard = self.shards.route(&key);
+        // the full key expression, for the filters configured by key expression
+        let full_key = if self.rate_limiter.is_some() || self.deadband.is_some() {
+            self.shards.full_key_expr(&key)
+        } else {
+            None
+        };
+        let measurement = self.measurement(key);
+
+        // guard against timestamps too far in the future
+        let timestamp = match self.check_clock_skew(&measurement, timestamp, false) {
+            Admission::Accept(timestamp) => timestamp,
+            Admission::Quarantine => {
+                if let Some(quotas) = &self.quotas {
+                    if !self
+                        .check_quarantine_quotas(quotas, shard, &measurement, &value)
+                        .await?
+                    {
+                        return Ok(WriteOutcome::Filtered);
+                    }
+                }
+                let influx_time = timestamp.get_time().to_duration().as_nanos();
+                let point = Point::Quarantined(&measurement);
+                self.write_point(shard, point, influx_time, &value, &timestamp)
+                    .await?;
+                if let Some(quotas) = &self.quotas {
+                    quotas.on_quarantined(&measurement);
+                }
+                return Ok(WriteOutcome::Quarantined);
+            }
+            Admission::Reject => return Ok(WriteOutcome::Filtered),
+        };
+
+        // Note: assume that uhlc timestamp was generated by a clock using UNIX_EPOCH (that's the case by default)
+        let influx_time = timestamp.get_time().to_duration().as_nanos();
+
+        // limit the rate of the samples written for the key
+        let value = match (&self.rate_limiter, &full_key) {
+            (Some(limiter), Some(full_key)) => {
+                match limiter.check(full_key, &measurement, &value, influx_time) {
+                    RateDecision::Write => value,
+                    // an average isn't an integer, nor necessarily in the sample's format
+                    RateDecision::WriteAverage(average) => {
+                        Value::from(average.to_string()).encoding(KnownEncoding::AppFloat.into())
+                    }
+                    RateDecision::Drop => {
+                        debug!(
+                            "Received a value for {:?} faster than its min interval; drop it",
+                            measurement
+                        );
+                        return Ok(WriteOutcome::Filtered);
+                    }
+                }
+            }
+            _ => value,
+        };
+
+        let outcome = self
+            .put_filtered(
+                shard,
+                &measurement,
+                full_key.as_deref(),
+                value,
+                timestamp,
+                influx_time,
+            )
+            .await;
+        // the samples of a rate window closed by a sample which is not written are discarded,
+        // not to be averaged with the next ones
+        if let (Some(limiter), Some(full_key)) = (&self.rate_limiter, &full_key) {
+            if !matches!(outcome, Ok(WriteOutcome::Stored(_))) {
+                limiter.on_skipped(full_key, &measurement, influx_time);
+            }
+        }
+        outcome
+    }
+
+    // Write a value (possibly averaged by the rate limiter) unless skipped by the other filters,
+    // the quotas, or as outdated
+    async fn put_filtered(
+        &self,
+        shard: Option<&Shard>,
+        measurement: &str,
+        full_key: Option<&keyexpr>,
+        value: Value,
+        timestamp: Timestamp,
+        influx_time: u128,
+    ) -> ZResult<WriteOutcome> {
+        // skip the sample if its numeric value is within the deadband of the last one written for the key
+        let number = match (&self.deadband, full_key) {
+            (Some(filter), Some(full_key)) if filter.applies_to(full_key) => parse_number(&value),
+            _ => None,
+        };
+        if let (Some(filter), Some(full_key), Some(number)) = (&self.deadband, &full_key, number) {
+            if filter.is_within(full_key, measurement, number, influx_time) {
+                debug!(
+                    "Received a value for {:?} within the deadband of the last one; skip it",
+                    measurement
+                );
+                return Ok(WriteOutcome::Filtered);
+            }
+        }
+
+        // skip the sample if its value is the same than the last one written for the key
+        let digest = match &self.change_filter {
+            Some(filter) => {
+                let digest = ChangeFilter::digest(
+                    u8::from(*value.encoding.prefix()),
+                    value.encoding.suffix(),
+                    &value.payload.contiguous(),
+                );
+                // the last value of a key not cached (e.g. created by another writer, or evicted) is read from InfluxDB
+                let unchanged = match filter.is_unchanged(measurement, &digest, influx_time) {
+                    Some(unchanged) => unchanged,
+                    None => {
+                        let last = self.get_last_value(shard, measurement).await?;
+                        filter.seed_and_check(measurement, last, &digest, influx_time)
+                    }
+                };
+                if unchanged {
+                    debug!("Received an unchanged value for {:?}; skip it", measurement);
+                    return Ok(WriteOutcome::Filtered);
+                }
+                Some(digest)
+            }
+            None => None,
+        };
 
-        // Note: assume that uhlc timestamp was generated by a clock using UNIX_EPOCH (that's the case by default)
-        let influx_time = timestamp.get_time().to_duration().as_nanos();
+        // enforce the quotas of the storage
+        if let Some(quotas) = &self.quotas {
+            if !self
+                .check_quotas(quotas, shard, measurement, &value)
+                .await?
+            {
+                return Ok(WriteOutcome::Filtered);
+            }
+        }
 
         // get timestamp of deletion of this measurement, if any
-        if let Some(del_time) = self.get_deletion_timestamp(measurement.as_str()).await? {
+        // (a write-only storage can't read it: the out-of-order samples older than a deletion are stored)
+        let del_time = if self.settings.privilege == Privilege::Write {
+            None
+        } else {
+            self.get_deletion_timestamp(shard, measurement).await?
+        };
+        if let Some(del_time) = del_time {
             // ignore sample if oldest than the deletion
             if timestamp < del_time {
                 debug!(
                     "Received a value for {:?} with timestamp older than its deletion; ignore it",
                     measurement
                 );
-                return Ok(StorageInsertionResult::Outdated);
+                return Ok(WriteOutcome::Outdated);
+            }
+        }
+
+        // in "latest" history mode, ignore sample if older than the current one
+        if self.settings.history == HistoryMode::Latest
+            && self.has_put_after(shard, measurement, influx_time).await?
+        {
+            debug!(
+                "Received a value for {:?} with timestamp older than the stored one; ignore it",
+                measurement
+            );
+            return Ok(WriteOutcome::Outdated);
+        }
+
+        // avoid (or resolve) a collision with another sample's point at the same time
+        let influx_time = match self
+            .collision_free_time(shard, measurement, influx_time, &timestamp)
+            .await?
+        {
+            Some(influx_time) => influx_time,
+            None => {
+                debug!(
+                    "Received a value for {:?} colliding with a stored one; ignore it",
+                    measurement
+                );
+                return Ok(WriteOutcome::Outdated);
+            }
+        };
+
+        let point = Point::Key(measurement);
+        self.write_point(shard, point, influx_time, &value, &timestamp)
+            .await?;
+        if let (Some(filter), Some(digest)) = (&self.change_filter, digest) {
+            filter.on_written(measurement, digest, influx_time);
+        }
+        if let (Some(filter), Some(number)) = (&self.deadband, number) {
+            filter.on_written(measurement, number, influx_time);
+        }
+        if let (Some(limiter), Some(full_key)) = (&self.rate_limiter, full_key) {
+            limiter.on_written(full_key, measurement, influx_time);
+        }
+        if let Some(quotas) = &self.quotas {
+            quotas.on_written(measurement, self.settings.history == HistoryMode::Latest);
+        }
+
+        // in "latest" history mode, the new point replaces the previous ones (including a deletion marker)
+        // Note: InfluxDB has no transaction, thus the write and the deletion are not atomic:
+        // meanwhile (or if the deletion fails) the previous points remain, until deleted by the next PUT on the key.
+        // That's harmless for the GETs without time range, replying with the latest point only.
+        if self.settings.history == HistoryMode::Latest {
+            let query = InfluxRQuery::new(influxql::delete_before(measurement, influx_time));
+            debug!(
+                "Replace previous points of {:?} with Influx query: {:?}",
+                measurement, query
+            );
+            if let Err(e) = self.write(shard, &query).await {
+                // the new point is written anyway
+                let e = zerror!(
+                    "Failed to delete previous points of measurement '{}' from InfluxDb storage : {}",
+                    measurement,
+                    e
+                );
+                warn!("{}", e);
+                self.stats.record_error(&e);
             }
+            return Ok(WriteOutcome::Stored(StorageInsertionResult::Replaced));
+        }
+        Ok(WriteOutcome::Stored(StorageInsertionResult::Inserted))
+    }
+
+    fn check_clock_skew(
+        &self,
+        measurement: &str,
+        timestamp: Timestamp,
+        is_delete: bool,
+    ) -> Admission {
+        match &self.clock_skew {
+            Some(guard) => guard.check(measurement, timestamp, is_delete),
+            None => Admission::Accept(timestamp),
         }
+    }
 
+    // Write a value as a point of a key's measurement, or of the quarantine
+    async fn write_point(
+        &self,
+        shard: Option<&Shard>,
+        point: Point<'_>,
+        influx_time: u128,
+        value: &Value,
+        timestamp: &Timestamp,
+    ) -> ZResult<()> {
         // encode the value as a string to be stored in InfluxDB, converting to base64 if the buffer is not a UTF-8 string
         let (base64, strvalue) = match String::from_utf8(value.payload.contiguous().into_owned()) {
             Ok(s) => (false, s),
             Err(err) => (true, b64_std_engine.encode(err.into_bytes())),
         };
+        let written_bytes = strvalue.len() as u64;
 
         // Note: tags are stored as strings in InfluxDB, while fileds are typed.
         // For simpler/faster deserialization, we store encoding, timestamp and base64 as fields.
-        // while the kind is stored as a tag to be indexed by InfluxDB and have faster queries on it.
-        let query = InfluxWQuery::new(
-            InfluxTimestamp::Nanoseconds(influx_time),
-            measurement.clone(),
-        )
-        .add_tag("kind", "PUT")
-        .add_field("timestamp", timestamp.to_string())
-        .add_field("encoding_prefix", u8::from(*value.encoding.prefix()))
-        .add_field("encoding_suffix", value.encoding.suffix())
-        .add_field("base64", base64)
-        .add_field("value", strvalue);
+        // while the kind and the source (i.e. the timestamp's ID) are stored as tags
+        // to be indexed by InfluxDB and have faster queries on them.
+        // Note: the quarantined samples are tagged with the measurement of their key.
+        let (measurement, mut query) = match point {
+            Point::Key(measurement) => (
+                measurement,
+                InfluxWQuery::new(InfluxTimestamp::Nanoseconds(influx_time), measurement),
+            ),
+            Point::Quarantined(measurement) => (
+                measurement,
+                InfluxWQuery::new(
+                    InfluxTimestamp::Nanoseconds(influx_time),
+                    QUARANTINE_MEASUREMENT,
+                )
+                .add_tag("key", measurement),
+            ),
+        };
+        query = query
+            .add_tag("kind", "PUT")
+            .add_tag("source", timestamp.get_id().to_string())
+            .add_field("timestamp", timestamp.to_string())
+            .add_field("encoding_prefix", u8::from(*value.encoding.prefix()))
+            .add_field("encoding_suffix", value.encoding.suffix())
+            .add_field("base64", base64)
+            .add_field("value", strvalue);
         debug!("Put {:?} with Influx query: {:?}", measurement, query);
-        if let Err(e) = self.client.query(&query).await {
+        let result = self.write(shard, &query).await;
+        if let Err(e) = result {
             bail!(
                 "Failed to put Value for {:?} in InfluxDb storage : {}",
                 measurement,
                 e
             )
-        } else {
-            Ok(StorageInsertionResult::Inserted)
         }
+        StorageStats::add(&self.stats.bytes_written, written_bytes);
+        if base64 {
+            StorageStats::incr(&self.stats.base64_payloads);
+        }
+        Ok(())
     }
 
-    async fn delete(
+    async fn delete_key(
         &mut self,
         key: Option<OwnedKeyExpr>,
         timestamp: Timestamp,
-    ) -> ZResult<StorageInsertionResult> {
-        let measurement = key.unwrap_or_else(|| OwnedKeyExpr::from_str(NONE_KEY).unwrap());
+    ) -> ZResult<WriteOutcome> {
+        self.setup.check_ready()?;
+        let shard = self.shards.route(&key);
+        let measurement = self.measurement(key);
+
+        // guard against timestamps too far in the future (a deletion can't be quarantined)
+        let timestamp = match self.check_clock_skew(&measurement, timestamp, true) {
+            Admission::Accept(timestamp) => timestamp,
+            _ => return Ok(WriteOutcome::Filtered),
+        };
 
         // Note: assume that uhlc timestamp was generated by a clock using UNIX_EPOCH (that's the case by default)
         let influx_time = timestamp.get_time().to_duration().as_nanos();
 
         // delete all points from the measurement that are older than this DELETE message
         // (in case more recent PUT have been recevived un-ordered)
-        let query = InfluxRQuery::new(format!(
-            r#"DELETE FROM "{}" WHERE time < {}"#,
-            measurement, influx_time
-        ));
+        let query = InfluxRQuery::new(influxql::delete_before(&measurement, influx_time));
         debug!("Delete {:?} with Influx query: {:?}", measurement, query);
-        if let Err(e) = self.client.query(&query).await {
+        let result = self.write(shard, &query).await;
+        if let Err(e) = result {
             bail!(
                 "Failed to delete points for measurement '{}' from InfluxDb storage : {}",
                 measurement,
@@ -474,35 +1525,52 @@ impl Storage for InfluxDbStorage {
             "Mark measurement {} as deleted at time {}",
             measurement, influx_time
         );
-        if let Err(e) = self.client.query(&query).await {
+        let result = self.write(shard, &query).await;
+        if let Err(e) = result {
             bail!(
                 "Failed to mark measurement {:?} as deleted : {}",
                 measurement,
                 e
             )
         }
+        if let Some(filter) = &self.change_filter {
+            filter.on_deleted(&measurement);
+        }
+        if let Some(filter) = &self.deadband {
+            filter.on_deleted(&measurement);
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.on_deleted(&measurement);
+        }
+        if let Some(quotas) = &self.quotas {
+            quotas.on_deleted(&measurement);
+        }
         // schedule the drop of measurement later in the future, if it's empty
-        let _ = self.schedule_measurement_drop(measurement.as_str()).await;
-        Ok(StorageInsertionResult::Deleted)
+        let _ = self
+            .schedule_measurement_drop(shard, measurement.as_str(), timestamp)
+            .await;
+        Ok(WriteOutcome::Stored(StorageInsertionResult::Deleted))
     }
 
-    async fn get(
+    async fn get_values(
         &mut self,
         key: Option<OwnedKeyExpr>,
         parameters: &str,
     ) -> ZResult<Vec<StoredData>> {
+        self.setup.check_ready()?;
         let measurement = match key.clone() {
             Some(k) => k,
             None => OwnedKeyExpr::from_str(NONE_KEY).unwrap(),
         };
         // convert the key expression into an Influx regex
-        let regex = key_exprs_to_influx_regex(&[&KeyExpr::from(measurement)]);
+        let regex =
+            influxql::key_exprs_to_regex(&self.measurement_prefix, &[&KeyExpr::from(measurement)]);
 
         // construct the Influx query clauses from the parameters
         let clauses = clauses_from_parameters(parameters)?;
 
         // the Influx query
-        let influx_query_str = format!("SELECT * FROM {regex} {clauses}");
+        let influx_query_str = influxql::select_all(&regex, &clauses);
         let influx_query = InfluxRQuery::new(&influx_query_str);
 
         // the expected JSon type resulting from the query
@@ -519,171 +1587,478 @@ impl Storage for InfluxDbStorage {
         }
         debug!("Get {:?} with Influx query: {}", key, influx_query_str);
         let mut result = Vec::new();
-        match self.client.json_query(influx_query).await {
-            Ok(mut query_result) => {
-                while !query_result.results.is_empty() {
-                    match query_result.deserialize_next::<ZenohPoint>() {
-                        Ok(retn) => {
-                            // for each serie
-                            for serie in retn.series {
-                                // get the key expression from the serie name
-                                let ke = match self.keyexpr_from_serie(&serie.name) {
-                                    Ok(k) => k,
-                                    Err(e) => {
-                                        error!(
-                                            "Error replying with serie '{}' : {}",
-                                            serie.name, e
-                                        );
+        for shard in self.shards.fan_out(&key) {
+            let query_result = self.read(shard, &influx_query).await;
+            match query_result {
+                Ok(mut query_result) => {
+                    while !query_result.results.is_empty() {
+                        match query_result.deserialize_next::<ZenohPoint>() {
+                            Ok(retn) => {
+                                // for each serie
+                                for serie in retn.series {
+                                    // the quarantined samples and the tombstones are not visible
+                                    if is_internal_measurement(&serie.name) {
                                         continue;
                                     }
-                                };
-                                debug!("Replying {} values for {:?}", serie.values.len(), ke);
-                                // for each point
-                                for zpoint in serie.values {
-                                    // get the encoding
-                                    let encoding_prefix =
-                                        zpoint.encoding_prefix.try_into().map_err(|_| {
-                                            zerror!("Unknown encoding {}", zpoint.encoding_prefix)
-                                        })?;
-                                    let encoding = if zpoint.encoding_suffix.is_empty() {
-                                        Encoding::Exact(encoding_prefix)
-                                    } else {
-                                        Encoding::WithSuffix(
-                                            encoding_prefix,
-                                            zpoint.encoding_suffix.into(),
-                                        )
+                                    // get the key expression from the serie name
+                                    let ke = match self.keyexpr_from_serie(&serie.name) {
+                                        Ok(k) => k,
+                                        Err(e) => {
+                                            error!(
+                                                "Error replying with serie '{}' : {}",
+                                                serie.name, e
+                                            );
+                                            continue;
+                                        }
                                     };
-                                    // get the payload
-                                    let payload = if zpoint.base64 {
-                                        match b64_std_engine.decode(zpoint.value) {
-                                            Ok(v) => ZBuf::from(v),
+                                    debug!("Replying {} values for {:?}", serie.values.len(), ke);
+                                    // for each point
+                                    for zpoint in serie.values {
+                                        // get the encoding
+                                        let encoding_prefix =
+                                            zpoint.encoding_prefix.try_into().map_err(|_| {
+                                                zerror!(
+                                                    "Unknown encoding {}",
+                                                    zpoint.encoding_prefix
+                                                )
+                                            })?;
+                                        let encoding = if zpoint.encoding_suffix.is_empty() {
+                                            Encoding::Exact(encoding_prefix)
+                                        } else {
+                                            Encoding::WithSuffix(
+                                                encoding_prefix,
+                                                zpoint.encoding_suffix.into(),
+                                            )
+                                        };
+                                        // get the payload
+                                        let payload = if zpoint.base64 {
+                                            match b64_std_engine.decode(zpoint.value) {
+                                                Ok(v) => ZBuf::from(v),
+                                                Err(e) => {
+                                                    warn!(
+                                                        r#"Failed to decode zenoh base64 Value from Influx point {} with timestamp="{}": {}"#,
+                                                        serie.name, zpoint.timestamp, e
+                                                    );
+                                                    continue;
+                                                }
+                                            }
+                                        } else {
+                                            ZBuf::from(zpoint.value.into_bytes())
+                                        };
+                                        // get the timestamp
+                                        let timestamp = match Timestamp::from_str(&zpoint.timestamp)
+                                        {
+                                            Ok(t) => t,
                                             Err(e) => {
                                                 warn!(
-                                                    r#"Failed to decode zenoh base64 Value from Influx point {} with timestamp="{}": {}"#,
+                                                    r#"Failed to decode zenoh Timestamp from Influx point {} with timestamp="{}": {:?}"#,
                                                     serie.name, zpoint.timestamp, e
                                                 );
                                                 continue;
                                             }
-                                        }
-                                    } else {
-                                        ZBuf::from(zpoint.value.into_bytes())
-                                    };
-                                    // get the timestamp
-                                    let timestamp = match Timestamp::from_str(&zpoint.timestamp) {
-                                        Ok(t) => t,
-                                        Err(e) => {
-                                            warn!(
-                                                r#"Failed to decode zenoh Timestamp from Influx point {} with timestamp="{}": {:?}"#,
-                                                serie.name, zpoint.timestamp, e
-                                            );
-                                            continue;
-                                        }
-                                    };
-                                    let value = Value::new(payload).encoding(encoding);
-                                    result.push(StoredData { value, timestamp });
+                                        };
+                                        let value = Value::new(payload).encoding(encoding);
+                                        result.push(StoredData { value, timestamp });
+                                    }
                                 }
                             }
-                        }
-                        Err(e) => {
-                            bail!(
-                                "Failed to parse result of InfluxDB query '{}': {}",
-                                influx_query_str,
-                                e
-                            )
+                            Err(e) => {
+                                bail!(
+                                    "Failed to parse result of InfluxDB query '{}': {}",
+                                    influx_query_str,
+                                    e
+                                )
+                            }
                         }
                     }
                 }
+                Err(e) => bail!(
+                    "Failed to query InfluxDb with '{}' : {}",
+                    influx_query_str,
+                    e
+                ),
+            }
+        }
+        Ok(result)
+    }
+
+    async fn get_entries(&self) -> ZResult<Vec<(Option<OwnedKeyExpr>, Timestamp)>> {
+        self.setup.check_ready()?;
+        let mut result = Vec::new();
+        for shard in self.shards.everywhere() {
+            let mut entries = Vec::new();
+            // page through the measurements, not to load the whole database at once
+            let mut pager = MeasurementsPager::new(Listing::Keys);
+            loop {
+                let measurements = self.next_measurements(shard, &mut pager).await?;
+                if measurements.is_empty() {
+                    break;
+                }
+                self.get_last_timestamps(shard, &measurements, &mut entries)
+                    .await?;
+            }
+            // page through the tombstones too
+            let mut index: HashMap<Option<OwnedKeyExpr>, usize> = entries
+                .iter()
+                .enumerate()
+                .map(|(i, (ke, _))| (ke.clone(), i))
+                .collect();
+            let mut pager = MeasurementsPager::new(Listing::Tombstones);
+            loop {
+                let measurements = self.next_measurements(shard, &mut pager).await?;
+                if measurements.is_empty() {
+                    break;
+                }
+                self.merge_tombstones(shard, &measurements, &mut entries, &mut index)
+                    .await?;
+            }
+            result.append(&mut entries);
+        }
+        Ok(result)
+    }
+
+    // The next page of the measurements names (following the last name of the previous page, as sorted by InfluxDB),
+    // or an empty page once all have been listed
+    async fn next_measurements(
+        &self,
+        shard: Option<&Shard>,
+        pager: &mut MeasurementsPager,
+    ) -> ZResult<Vec<String>> {
+        if pager.done {
+            return Ok(Vec::new());
+        }
+        // the names not following the last one are sorted first (see influxql::following_names_regex()):
+        // skip the pages made only of them, then the remaining ones
+        let mut offset = 0;
+        loop {
+            let page = self
+                .get_measurements(shard, pager.listing, pager.last.as_deref(), offset)
+                .await?;
+            let full = page.len() == ENTRIES_PAGE_SIZE;
+            let measurements: Vec<String> = match &pager.last {
+                Some(last) => page.into_iter().filter(|m| m > last).collect(),
+                None => page,
+            };
+            if measurements.is_empty() && full {
+                offset += ENTRIES_PAGE_SIZE;
+                continue;
+            }
+            pager.done = !full;
+            if let Some(last) = measurements.last() {
+                pager.last = Some(last.clone());
             }
+            return Ok(measurements);
+        }
+    }
+
+    // A page of the measurements names (or of the dropped ones, with a tombstone), following a name if any
+    async fn get_measurements(
+        &self,
+        shard: Option<&Shard>,
+        listing: Listing,
+        after: Option<&str>,
+        offset: usize,
+    ) -> ZResult<Vec<String>> {
+        #[derive(Deserialize, Debug)]
+        struct Measurement {
+            name: String,
+        }
+        #[derive(Deserialize, Debug)]
+        struct TagValue {
+            value: String,
+        }
+
+        let influx_query_str = match listing {
+            Listing::Keys => influxql::show_measurements(
+                &self.measurement_prefix,
+                ENTRIES_PAGE_SIZE,
+                offset,
+                after,
+            ),
+            Listing::Tombstones => influxql::show_tombstoned_measurements(
+                TOMBSTONES_MEASUREMENT,
+                &self.measurement_prefix,
+                ENTRIES_PAGE_SIZE,
+                offset,
+                after,
+            ),
+        };
+        let influx_query = InfluxRQuery::new(&influx_query_str);
+        debug!("Get measurements with Influx query: {}", influx_query_str);
+        let mut query_result = match self.read(shard, &influx_query).await {
+            Ok(query_result) => query_result,
             Err(e) => bail!(
                 "Failed to query InfluxDb with '{}' : {}",
                 influx_query_str,
                 e
             ),
+        };
+        let names = match listing {
+            Listing::Keys => query_result.deserialize_next::<Measurement>().map(|retn| {
+                retn.series
+                    .into_iter()
+                    .flat_map(|serie| serie.values)
+                    .map(|m| m.name)
+                    .collect()
+            }),
+            Listing::Tombstones => query_result.deserialize_next::<TagValue>().map(|retn| {
+                retn.series
+                    .into_iter()
+                    .flat_map(|serie| serie.values)
+                    .map(|v| v.value)
+                    .collect()
+            }),
+        };
+        match names {
+            Ok(names) => Ok(names),
+            Err(e) => bail!(
+                "Failed to parse result of InfluxDB query '{}': {}",
+                influx_query_str,
+                e
+            ),
         }
-        Ok(result)
     }
 
-    async fn get_all_entries(&self) -> ZResult<Vec<(Option<OwnedKeyExpr>, Timestamp)>> {
-        let mut result = Vec::new();
+    // The latest timestamp of each measurement (including the deleted ones, with their deletion timestamp)
+    async fn get_last_timestamps(
+        &self,
+        shard: Option<&Shard>,
+        measurements: &[String],
+        result: &mut Vec<(Option<OwnedKeyExpr>, Timestamp)>,
+    ) -> ZResult<()> {
+        #[derive(Deserialize, Debug)]
+        struct LastTimestamp {
+            last: String,
+        }
 
-        // the Influx query
-        let influx_query_str = format!("SELECT * FROM {}", *INFLUX_REGEX_ALL);
+        let influx_query_str = influxql::select_last_timestamps(measurements);
         let influx_query = InfluxRQuery::new(&influx_query_str);
+        debug!(
+            "Get latest timestamps of {} measurements with Influx query: {}",
+            measurements.len(),
+            influx_query_str
+        );
+        let mut query_result = match self.read(shard, &influx_query).await {
+            Ok(query_result) => query_result,
+            Err(e) => bail!(
+                "Failed to query InfluxDb with '{}' : {}",
+                influx_query_str,
+                e
+            ),
+        };
+        let retn = match query_result.deserialize_next::<LastTimestamp>() {
+            Ok(retn) => retn,
+            Err(e) => bail!(
+                "Failed to parse result of InfluxDB query '{}': {}",
+                influx_query_str,
+                e
+            ),
+        };
+        // one serie per measurement
+        for serie in retn.series {
+            if is_internal_measurement(&serie.name) {
+                continue;
+            }
+            let ke = match self.keyexpr_from_serie(&serie.name) {
+                Ok(ke) => ke,
+                Err(e) => {
+                    error!("Error replying with serie '{}' : {}", serie.name, e);
+                    continue;
+                }
+            };
+            for point in serie.values {
+                match Timestamp::from_str(&point.last) {
+                    Ok(timestamp) => result.push((ke.clone(), timestamp)),
+                    Err(e) => warn!(
+                        r#"Failed to decode zenoh Timestamp from Influx point {} with timestamp="{}": {:?}"#,
+                        serie.name, point.last, e
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
 
-        // the expected JSon type resulting from the query
+    // Add the deletion timestamp of a page of dropped measurements to the entries of a shard
+    // (indexed by key), unless the key has been put again since
+    async fn merge_tombstones(
+        &self,
+        shard: Option<&Shard>,
+        measurements: &[String],
+        entries: &mut Vec<(Option<OwnedKeyExpr>, Timestamp)>,
+        index: &mut HashMap<Option<OwnedKeyExpr>, usize>,
+    ) -> ZResult<()> {
         #[derive(Deserialize, Debug)]
-        struct ZenohPoint {
-            #[allow(dead_code)]
-            // NOTE: "kind" is present within InfluxDB and used in query clauses, but not read in Rust...
-            kind: String,
-            timestamp: String,
+        struct Tombstone {
+            measurement: String,
         }
-        debug!("Get all entries with Influx query: {}", influx_query_str);
-        match self.client.json_query(influx_query).await {
-            Ok(mut query_result) => {
-                while !query_result.results.is_empty() {
-                    match query_result.deserialize_next::<ZenohPoint>() {
-                        Ok(retn) => {
-                            // for each serie
-                            for serie in retn.series {
-                                // get the key expression from the serie name
-                                match self.keyexpr_from_serie(&serie.name) {
-                                    Ok(ke) => {
-                                        debug!(
-                                            "Replying {} values for {:?}",
-                                            serie.values.len(),
-                                            ke
-                                        );
-                                        // for each point in the serie
-                                        for zpoint in serie.values {
-                                            // get the timestamp (ignore the point if failing)
-                                            match Timestamp::from_str(&zpoint.timestamp) {
-                                                Ok(timestamp) => {
-                                                    result.push((ke.clone(), timestamp))
-                                                }
-                                                Err(e) => warn!(
-                                                    r#"Failed to decode zenoh Timestamp from Influx point {} with timestamp="{}": {:?}"#,
-                                                    serie.name, zpoint.timestamp, e
-                                                ),
-                                            };
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Error replying with serie '{}' : {}", serie.name, e)
-                                    }
-                                };
-                            }
-                        }
-                        Err(e) => {
-                            bail!(
-                                "Failed to parse result of InfluxDB query '{}': {}",
-                                influx_query_str,
-                                e
-                            )
-                        }
-                    }
-                }
-                Ok(result)
-            }
+        #[derive(Deserialize, Debug)]
+        struct LastTimestamp {
+            last: String,
+        }
+
+        let influx_query_str = influxql::select_tombstones(TOMBSTONES_MEASUREMENT, measurements);
+        let influx_query = InfluxRQuery::new(&influx_query_str);
+        debug!("Get tombstones with Influx query: {}", influx_query_str);
+        let mut query_result = match self.read(shard, &influx_query).await {
+            Ok(query_result) => query_result,
             Err(e) => bail!(
                 "Failed to query InfluxDb with '{}' : {}",
                 influx_query_str,
                 e
             ),
+        };
+        let retn = match query_result.deserialize_next_tagged::<Tombstone, LastTimestamp>() {
+            Ok(retn) => retn,
+            Err(e) => bail!(
+                "Failed to parse result of InfluxDB query '{}': {}",
+                influx_query_str,
+                e
+            ),
+        };
+        // one serie per dropped measurement
+        for serie in retn.series {
+            let ke = match self.keyexpr_from_serie(&serie.tags.measurement) {
+                Ok(ke) => ke,
+                Err(e) => {
+                    error!(
+                        "Error replying with tombstone of '{}' : {}",
+                        serie.tags.measurement, e
+                    );
+                    continue;
+                }
+            };
+            for point in serie.values {
+                let timestamp = match Timestamp::from_str(&point.last) {
+                    Ok(timestamp) => timestamp,
+                    Err(e) => {
+                        warn!(
+                            r#"Failed to decode zenoh Timestamp from tombstone of {} with timestamp="{}": {:?}"#,
+                            serie.tags.measurement, point.last, e
+                        );
+                        continue;
+                    }
+                };
+                match index.get(&ke) {
+                    Some(&i) => {
+                        if entries[i].1 < timestamp {
+                            entries[i].1 = timestamp;
+                        }
+                    }
+                    None => {
+                        index.insert(ke.clone(), entries.len());
+                        entries.push((ke.clone(), timestamp));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The latest point of a measurement (possibly a deletion marker)
+#[derive(Deserialize, Debug)]
+struct LastPoint {
+    kind: String,
+    timestamp: String,
+    encoding_prefix: Option<ZInt>,
+    encoding_suffix: Option<String>,
+    base64: Option<bool>,
+    value: Option<String>,
+}
+
+// The digest and time of the value of the latest point of a measurement (see ChangeFilter),
+// or None if it's a deletion marker
+fn last_value(measurement: &str, point: LastPoint) -> ZResult<Option<(Digest, u128)>> {
+    if point.kind != "PUT" {
+        return Ok(None);
+    }
+    let (encoding_prefix, value) = match (point.encoding_prefix, point.value) {
+        (Some(encoding_prefix), Some(value)) => (encoding_prefix, value),
+        _ => return Ok(None),
+    };
+    let payload = if point.base64 == Some(true) {
+        b64_std_engine.decode(value).map_err(|e| {
+            zerror!(
+                "Failed to decode base64 value of latest point of measurement {} : {}",
+                measurement,
+                e
+            )
+        })?
+    } else {
+        value.into_bytes()
+    };
+    let timestamp = point.timestamp.parse::<Timestamp>().map_err(|err| {
+        zerror!(
+            "Failed to parse timestamp of latest point of measurement {} : {}",
+            measurement,
+            err.cause
+        )
+    })?;
+    let digest = ChangeFilter::digest(
+        encoding_prefix as u8,
+        point.encoding_suffix.as_deref().unwrap_or_default(),
+        &payload,
+    );
+    Ok(Some((
+        digest,
+        timestamp.get_time().to_duration().as_nanos(),
+    )))
+}
+
+// Where a value is written: as a point of its key's measurement, or quarantined
+#[derive(Clone, Copy)]
+enum Point<'a> {
+    Key(&'a str),
+    Quarantined(&'a str),
+}
+
+// The measurements names listed by a MeasurementsPager
+#[derive(Clone, Copy)]
+enum Listing {
+    // the measurements of the keys
+    Keys,
+    // the dropped measurements, with a tombstone
+    Tombstones,
+}
+
+// The paging through the measurements of a shard (see InfluxDbStorage::next_measurements())
+struct MeasurementsPager {
+    listing: Listing,
+    // the last name of the previous page
+    last: Option<String>,
+    done: bool,
+}
+
+impl MeasurementsPager {
+    fn new(listing: Listing) -> Self {
+        MeasurementsPager {
+            listing,
+            last: None,
+            done: false,
         }
     }
 }
 
+// The measurements of the storage not storing a key (quarantined samples and tombstones)
+fn is_internal_measurement(name: &str) -> bool {
+    name == QUARANTINE_MEASUREMENT || name == TOMBSTONES_MEASUREMENT
+}
+
 impl Drop for InfluxDbStorage {
     fn drop(&mut self) {
         debug!("Closing InfluxDB storage");
-        match self.on_closure {
+        // a storage not set up (yet) has nothing to close in InfluxDB
+        if self.setup.check_ready().is_err() {
+            return;
+        }
+        match self.settings.on_closure {
             OnClosure::DropDb => {
                 task::block_on(async move {
                     let db = self.admin_client.database_name();
                     debug!("Close InfluxDB storage, dropping database {}", db);
-                    let query = InfluxRQuery::new(format!(r#"DROP DATABASE "{db}""#));
-                    if let Err(e) = self.admin_client.query(&query).await {
+                    let query = InfluxRQuery::new(influxql::drop_database(db));
+                    if let Err(e) = self.admin_client.active().query(&query).await {
                         error!("Failed to drop InfluxDb database '{}' : {}", db, e)
                     }
                 });
@@ -695,8 +2070,12 @@ impl Drop for InfluxDbStorage {
                         "Close InfluxDB storage, dropping all series from database {}",
                         db
                     );
-                    let query = InfluxRQuery::new("DROP SERIES FROM /.*/");
-                    if let Err(e) = self.client.query(&query).await {
+                    let query = InfluxRQuery::new(influxql::drop_all_series(
+                        &self.measurement_prefix,
+                        TOMBSTONES_MEASUREMENT,
+                        QUARANTINE_MEASUREMENT,
+                    ));
+                    if let Err(e) = self.client.active().query(&query).await {
                         error!(
                             "Failed to drop all series from InfluxDb database '{}' : {}",
                             db, e
@@ -711,29 +2090,75 @@ impl Drop for InfluxDbStorage {
                 );
             }
         }
+        // the same strategy applies to the databases of the shards and of the mirrors
+        let remotes = self
+            .shards
+            .all()
+            .iter()
+            .map(|shard| (&shard.url, &shard.client))
+            .chain(self.mirrors.mirrors().iter().map(|m| (&m.url, &m.client)));
+        for (url, client) in remotes {
+            let db = client.database_name();
+            let query = match self.settings.on_closure {
+                OnClosure::DropDb => InfluxRQuery::new(influxql::drop_database(db)),
+                OnClosure::DropSeries => InfluxRQuery::new(influxql::drop_all_series(
+                    &self.measurement_prefix,
+                    TOMBSTONES_MEASUREMENT,
+                    QUARANTINE_MEASUREMENT,
+                )),
+                OnClosure::DoNothing => break,
+            };
+            debug!(
+                "Close InfluxDB storage, {:?} on database {} of {}",
+                self.settings.on_closure, db, url
+            );
+            if let Err(e) = task::block_on(client.query(&query)) {
+                error!(
+                    "Failed to {:?} on InfluxDb database '{}' of {} : {}",
+                    self.settings.on_closure, db, url, e
+                )
+            }
+        }
     }
 }
 
-// Scheduled dropping of a measurement after a timeout, if it's empty
+// The backend's credentials, if any
+fn volume_credentials(settings: &VolumeSettings) -> Option<(&str, &str)> {
+    settings
+        .credentials
+        .as_ref()
+        .map(|(username, password)| (username.as_str(), password.as_str()))
+}
+
+// Scheduled dropping of a measurement after a timeout, if it's empty,
+// keeping its deletion timestamp as a tombstone (on each database storing it)
 struct TimedMeasurementDrop {
-    client: Client,
+    clients: Vec<Client>,
     measurement: String,
+    deletion: Timestamp,
+    stats: Arc<StorageStats>,
 }
 
 #[async_trait]
 impl Timed for TimedMeasurementDrop {
     async fn run(&mut self) {
+        for client in &self.clients {
+            self.drop_if_empty(client).await;
+        }
+        StorageStats::decr(&self.stats.pending_drops);
+    }
+}
+
+impl TimedMeasurementDrop {
+    async fn drop_if_empty(&self, client: &Client) {
         #[derive(Deserialize, Debug, PartialEq)]
         struct QueryResult {
             kind: String,
         }
 
         // check if there is at least 1 point without "DEL" kind in the measurement
-        let query = InfluxRQuery::new(format!(
-            r#"SELECT "kind" FROM "{}" WHERE kind!='DEL' LIMIT 1"#,
-            self.measurement
-        ));
-        match self.client.json_query(query).await {
+        let query = InfluxRQuery::new(influxql::select_one_put(&self.measurement));
+        match client.json_query(query).await {
             Ok(mut result) => match result.deserialize_next::<QueryResult>() {
                 Ok(qr) => {
                     if !qr.series.is_empty() {
@@ -757,13 +2182,28 @@ impl Timed for TimedMeasurementDrop {
             }
         }
 
+        // keep the deletion timestamp, thus the key remains in get_all_entries()
+        let query = InfluxWQuery::new(
+            InfluxTimestamp::Nanoseconds(self.deletion.get_time().to_duration().as_nanos()),
+            TOMBSTONES_MEASUREMENT,
+        )
+        .add_tag("measurement", self.measurement.clone())
+        .add_field("timestamp", self.deletion.to_string());
+        if let Err(e) = client.query(&query).await {
+            warn!(
+                "Failed to write tombstone of measurement '{}' (can't drop it) : {}",
+                self.measurement, e
+            );
+            return;
+        }
+
         // drop the measurement
-        let query = InfluxRQuery::new(format!(r#"DROP MEASUREMENT "{}""#, self.measurement));
+        let query = InfluxRQuery::new(influxql::drop_measurement(&self.measurement));
         debug!(
             "Drop measurement {} after timeout with Influx query: {:?}",
             self.measurement, query
         );
-        if let Err(e) = self.client.query(&query).await {
+        if let Err(e) = client.query(&query).await {
             warn!(
                 "Failed to drop measurement '{}' from InfluxDb storage : {}",
                 self.measurement, e
@@ -772,8 +2212,28 @@ impl Timed for TimedMeasurementDrop {
     }
 }
 
+// Serialize some settings or statistics for the admin status
+fn json_or_null<T: serde::Serialize>(value: &T) -> serde_json::Value {
+    serde_json::to_value(value).unwrap_or(serde_json::Value::Null)
+}
+
 fn generate_db_name() -> String {
-    format!("zenoh_db_{}", Uuid::new_v4().simple())
+    format!("{GENERATED_DB_PREFIX}{}", Uuid::new_v4().simple())
+}
+
+// A database name derived from the storage's name and key expression, thus stable across restarts
+fn deterministic_db_name(storage_name: &str, key_expr: &keyexpr) -> String {
+    // FNV-1a (64 bits), being stable across versions and platforms (unlike std's DefaultHasher)
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let bytes = storage_name
+        .bytes()
+        .chain(std::iter::once(0))
+        .chain(key_expr.as_str().bytes());
+    for b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{GENERATED_DB_PREFIX}{hash:016x}")
 }
 
 async fn show_databases(client: &Client) -> ZResult<Vec<String>> {
@@ -781,7 +2241,7 @@ async fn show_databases(client: &Client) -> ZResult<Vec<String>> {
     struct Database {
         name: String,
     }
-    let query = InfluxRQuery::new("SHOW DATABASES");
+    let query = InfluxRQuery::new(influxql::show_databases());
     debug!("List databases with Influx query: {:?}", query);
     match client.json_query(query).await {
         Ok(mut result) => match result.deserialize_next::<Database>() {
@@ -811,9 +2271,10 @@ async fn is_db_existing(client: &Client, db_name: &str) -> ZResult<bool> {
 async fn create_db(
     client: &Client,
     db_name: &str,
-    storage_username: Option<String>,
+    storage_username: Option<&str>,
+    privilege: Privilege,
 ) -> ZResult<()> {
-    let query = InfluxRQuery::new(format!(r#"CREATE DATABASE "{db_name}""#));
+    let query = InfluxRQuery::new(influxql::create_database(db_name));
     debug!("Create Influx database: {}", db_name);
     if let Err(e) = client.query(&query).await {
         bail!(
@@ -825,10 +2286,12 @@ async fn create_db(
 
     // is a username is specified for storage access, grant him access to the database
     if let Some(username) = storage_username {
-        let query = InfluxRQuery::new(format!(r#"GRANT ALL ON "{db_name}" TO "{username}""#));
+        let query = InfluxRQuery::new(influxql::grant(privilege.as_influxql(), db_name, username));
         debug!(
-            "Grant access to {} on Influx database: {}",
-            username, db_name
+            "Grant {} access to {} on Influx database: {}",
+            privilege.as_influxql(),
+            username,
+            db_name
         );
         if let Err(e) = client.query(&query).await {
             bail!(
@@ -842,37 +2305,70 @@ async fn create_db(
     Ok(())
 }
 
-// Returns an InfluxDB regex (see https://docs.influxdata.com/influxdb/v1.8/query_language/explore-data/#regular-expressions)
-// corresponding to the list of path expressions. I.e.:
-// Replace "**" with ".*", "*" with "[^\/]*"  and "/" with "\/".
-// Concat each with "|", and surround the result with '/^' and '$/'.
-fn key_exprs_to_influx_regex(path_exprs: &[&keyexpr]) -> String {
-    let mut result = String::with_capacity(2 * path_exprs[0].len());
-    result.push_str("/^");
-    for (i, path_expr) in path_exprs.iter().enumerate() {
-        if i != 0 {
-            result.push('|');
-        }
-        let mut chars = path_expr.chars().peekable();
-        while let Some(c) = chars.next() {
-            match c {
-                '*' => {
-                    if let Some(c2) = chars.peek() {
-                        if c2 == &'*' {
-                            result.push_str(".*");
-                            chars.next();
-                        } else {
-                            result.push_str(".*")
-                        }
-                    }
-                }
-                '/' => result.push_str(r#"\/"#),
-                _ => result.push(c),
-            }
-        }
+async fn check_grants(
+    client: &Client,
+    db_name: &str,
+    username: &str,
+    privilege: Privilege,
+) -> ZResult<()> {
+    // Note: SHOW GRANTS returns unnamed series of (database, privilege) rows,
+    // that can't be deserialized with influxdb::Client::json_query()
+    #[derive(Deserialize)]
+    struct Response {
+        results: Vec<StatementResult>,
+    }
+    #[derive(Deserialize)]
+    struct StatementResult {
+        #[serde(default)]
+        series: Vec<Serie>,
+    }
+    #[derive(Deserialize)]
+    struct Serie {
+        #[serde(default)]
+        values: Vec<(String, String)>,
+    }
+
+    let query = InfluxRQuery::new(influxql::show_grants(username));
+    debug!(
+        "Check grants of {} with Influx query: {:?}",
+        username, query
+    );
+    let response = match client.query(&query).await {
+        Ok(response) => response,
+        // Note: the volume's credentials must be for an admin to check the grants
+        Err(e) => bail!(
+            "Failed to check the {} privilege of InfluxDb user {} on database '{}' (are the volume's credentials for an admin?) : {}",
+            privilege.as_influxql(),
+            username,
+            db_name,
+            e
+        ),
+    };
+    let grants: Response = serde_json::from_str(&response).map_err(|e| {
+        zerror!(
+            "Failed to parse grants of InfluxDb user {} : {}",
+            username,
+            e
+        )
+    })?;
+    let granted: Vec<String> = grants
+        .results
+        .into_iter()
+        .flat_map(|r| r.series)
+        .flat_map(|serie| serie.values)
+        .filter(|(database, _)| database == db_name)
+        .map(|(_, privilege)| privilege)
+        .collect();
+    if !granted.iter().any(|g| privilege.is_covered_by(g)) {
+        bail!(
+            "InfluxDb user {} is missing the {} privilege on database '{}' (granted: {:?})",
+            username,
+            privilege.as_influxql(),
+            db_name,
+            granted
+        )
     }
-    result.push_str("$/");
-    result
+    Ok(())
 }
 
 fn clauses_from_parameters(p: &str) -> ZResult<String> {
@@ -880,6 +2376,10 @@ fn clauses_from_parameters(p: &str) -> ZResult<String> {
     let time_range = p.time_range()?;
     let mut result = String::with_capacity(256);
     result.push_str("WHERE kind!='DEL'");
+    if let Some(source) = parameter_value(p, SOURCE_PARAMETER) {
+        result.push_str(" AND ");
+        result.push_str(&influxql::source_condition(source));
+    }
     match time_range {
         Some(TimeRange(start, stop)) => {
             match start {
@@ -913,6 +2413,25 @@ fn clauses_from_parameters(p: &str) -> ZResult<String> {
     Ok(result)
 }
 
+// Returns the value of a selector parameter (e.g. "_time" or "_timeout"), if present
+fn parameter_value<'a>(parameters: &'a str, name: &str) -> Option<&'a str> {
+    parameters
+        .split(|c| c == ';' || c == '&')
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+// Returns the timeout of a GET, specified with the "_timeout" selector parameter
+fn timeout_from_parameters(p: &str) -> ZResult<Option<Duration>> {
+    match parameter_value(p, TIMEOUT_PARAMETER) {
+        Some(t) => humantime::parse_duration(t).map(Some).map_err(|e| {
+            zerror!("Invalid `{}` parameter '{}': {}", TIMEOUT_PARAMETER, t, e).into()
+        }),
+        None => Ok(None),
+    }
+}
+
 fn write_timeexpr(s: &mut String, t: TimeExpr) {
     use humantime::format_rfc3339;
     use std::fmt::Write;
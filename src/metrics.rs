@@ -0,0 +1,284 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Export of the storages statistics in the OpenMetrics text format
+//! (see https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md),
+//! for scraping by Prometheus.
+
+use crate::stats::{LatencyHistogram, StorageStats, LATENCY_BUCKETS_MS};
+use async_std::channel::{bounded, Sender};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+use futures::future::{select, Either};
+use log::{debug, error, warn};
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, Weak};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The registry of the statistics of all the storages of a volume.
+pub(crate) struct MetricsRegistry {
+    volume: String,
+    // the storages are unregistered when dropped (i.e. when their stats are dropped)
+    storages: Mutex<Vec<(String, Weak<StorageStats>)>>,
+}
+
+impl MetricsRegistry {
+    pub(crate) fn new(volume: String) -> Self {
+        MetricsRegistry {
+            volume,
+            storages: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn register(&self, storage: &str, stats: &Arc<StorageStats>) {
+        if let Ok(mut storages) = self.storages.lock() {
+            storages.retain(|(_, s)| s.strong_count() > 0);
+            storages.push((storage.to_string(), Arc::downgrade(stats)));
+        }
+    }
+
+    /// Render the metrics as an OpenMetrics text document.
+    pub(crate) fn render(&self) -> String {
+        let storages: Vec<(String, Arc<StorageStats>)> = match self.storages.lock() {
+            Ok(storages) => storages
+                .iter()
+                .filter_map(|(name, s)| s.upgrade().map(|s| (name.clone(), s)))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let mut out = String::with_capacity(4096);
+        let volume_labels = format!(r#"volume="{}""#, escape_label(&self.volume));
+
+        write_header(
+            &mut out,
+            "zenoh_influxdb_storages",
+            "gauge",
+            "Number of storages in the volume",
+        );
+        let _ = writeln!(
+            out,
+            "zenoh_influxdb_storages{{{volume_labels}}} {}",
+            storages.len()
+        );
+
+        for (name, help, counter) in COUNTERS {
+            write_header(&mut out, name, "counter", help);
+            for (storage, stats) in &storages {
+                let _ = writeln!(
+                    out,
+                    r#"{name}_total{{{volume_labels},storage="{}"}} {}"#,
+                    escape_label(storage),
+                    StorageStats::get(counter(stats))
+                );
+            }
+        }
+
+        write_header(
+            &mut out,
+            "zenoh_influxdb_pending_measurement_drops",
+            "gauge",
+            "Number of measurements scheduled to be dropped",
+        );
+        for (storage, stats) in &storages {
+            let _ = writeln!(
+                out,
+                r#"zenoh_influxdb_pending_measurement_drops{{{volume_labels},storage="{}"}} {}"#,
+                escape_label(storage),
+                StorageStats::get(&stats.pending_drops)
+            );
+        }
+
+        for (name, help, histogram) in HISTOGRAMS {
+            write_header(&mut out, name, "histogram", help);
+            for (storage, stats) in &storages {
+                let labels = format!(r#"{volume_labels},storage="{}""#, escape_label(storage));
+                write_histogram(&mut out, name, &labels, histogram(stats));
+            }
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+// Names, descriptions and values of the counters
+#[allow(clippy::type_complexity)]
+const COUNTERS: [(&str, &str, fn(&StorageStats) -> &AtomicU64); 9] = [
+    (
+        "zenoh_influxdb_puts",
+        "Number of values put in the storage",
+        |s| &s.puts,
+    ),
+    (
+        "zenoh_influxdb_tombstones",
+        "Number of deletions (tombstones) written in the storage",
+        |s| &s.deletes,
+    ),
+    (
+        "zenoh_influxdb_gets",
+        "Number of queries on the storage",
+        |s| &s.gets,
+    ),
+    (
+        "zenoh_influxdb_outdated",
+        "Number of samples ignored as older than a deletion or than the stored value, or colliding with a kept one",
+        |s| &s.outdated,
+    ),
+    (
+        "zenoh_influxdb_filtered",
+        "Number of samples skipped by a filter (rate limit, deadband, unchanged value, quotas or clock skew guard)",
+        |s| &s.filtered,
+    ),
+    (
+        "zenoh_influxdb_quarantined",
+        "Number of samples written in a quarantine measurement",
+        |s| &s.quarantined,
+    ),
+    (
+        "zenoh_influxdb_errors",
+        "Number of failed operations on the storage",
+        |s| &s.errors,
+    ),
+    (
+        "zenoh_influxdb_retries",
+        "Number of InfluxDB requests retried after a transient error",
+        |s| &s.retries,
+    ),
+    (
+        "zenoh_influxdb_written_bytes",
+        "Total size of the values written in InfluxDB",
+        |s| &s.bytes_written,
+    ),
+];
+
+// Names, descriptions and values of the histograms
+#[allow(clippy::type_complexity)]
+const HISTOGRAMS: [(&str, &str, fn(&StorageStats) -> &LatencyHistogram); 2] = [
+    (
+        "zenoh_influxdb_write_latency_seconds",
+        "Latency of the InfluxDB write requests",
+        |s| &s.write_latency,
+    ),
+    (
+        "zenoh_influxdb_query_latency_seconds",
+        "Latency of the InfluxDB queries",
+        |s| &s.query_latency,
+    ),
+];
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "# HELP {name} {help}");
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &LatencyHistogram) {
+    let mut cumulative = 0;
+    for (i, count) in histogram.buckets().into_iter().enumerate() {
+        cumulative += count;
+        match LATENCY_BUCKETS_MS.get(i) {
+            Some(bound) => {
+                let le = *bound as f64 / 1000.0;
+                let _ = writeln!(out, r#"{name}_bucket{{{labels},le="{le}"}} {cumulative}"#);
+            }
+            None => {
+                let _ = writeln!(out, r#"{name}_bucket{{{labels},le="+Inf"}} {cumulative}"#);
+            }
+        }
+    }
+    let _ = writeln!(
+        out,
+        "{name}_sum{{{labels}}} {}",
+        histogram.sum().as_secs_f64()
+    );
+    let _ = writeln!(out, "{name}_count{{{labels}}} {}", histogram.count());
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// The HTTP server of the metrics, stopped (and its port released) when dropped.
+pub(crate) struct MetricsServer {
+    // never sent: the server stops when the channel is closed
+    _stop: Sender<()>,
+}
+
+/// Serve the metrics over HTTP on a local port, until the returned server is dropped.
+pub(crate) fn serve(registry: &Arc<MetricsRegistry>, port: u16) -> MetricsServer {
+    let registry = Arc::downgrade(registry);
+    let (stop, stopped) = bounded::<()>(1);
+    task::spawn(async move {
+        let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(
+                    "Failed to serve InfluxDB backend metrics on port {} : {}",
+                    port, e
+                );
+                return;
+            }
+        };
+        debug!(
+            "Serving InfluxDB backend metrics on http://127.0.0.1:{}/metrics",
+            port
+        );
+        loop {
+            // wait for a connection, unless the server is stopped
+            let accepted = match select(Box::pin(listener.accept()), Box::pin(stopped.recv())).await
+            {
+                Either::Left((accepted, _)) => accepted,
+                Either::Right(_) => break,
+            };
+            let registry = match registry.upgrade() {
+                Some(registry) => registry,
+                // the volume has been dropped
+                None => break,
+            };
+            match accepted {
+                Ok((stream, _)) => {
+                    task::spawn(async move {
+                        if let Err(e) = reply(stream, &registry).await {
+                            warn!("Failed to reply with InfluxDB backend metrics : {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!(
+                    "Failed to accept a connection for InfluxDB backend metrics : {}",
+                    e
+                ),
+            }
+        }
+        debug!("Stop serving InfluxDB backend metrics on port {}", port);
+    });
+    MetricsServer { _stop: stop }
+}
+
+async fn reply(mut stream: TcpStream, registry: &MetricsRegistry) -> std::io::Result<()> {
+    // whatever the request, reply with the metrics
+    let mut buf = [0u8; 1024];
+    let _ = stream.read(&mut buf).await?;
+    let body = registry.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await
+}
@@ -0,0 +1,252 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Mirroring of the writes of a storage to several independent InfluxDB instances.
+
+use crate::stats::LastError;
+use influxdb::Client;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AckPolicy {
+    // a write succeeds if it succeeded on all the targets
+    #[default]
+    All,
+    // a write succeeds if it succeeded on at least one target
+    Any,
+    // a write succeeds if it succeeded on a majority of the targets
+    Quorum,
+}
+
+impl AckPolicy {
+    // The number of targets that must acknowledge a write
+    fn required(&self, targets: usize) -> usize {
+        match self {
+            AckPolicy::All => targets,
+            AckPolicy::Any => 1,
+            AckPolicy::Quorum => targets / 2 + 1,
+        }
+    }
+}
+
+/// Counters of a mirroring target.
+#[derive(Default)]
+pub(crate) struct TargetStats {
+    writes: AtomicU64,
+    failed_writes: AtomicU64,
+    // writes acknowledged to zenoh, but that failed on this target (i.e. missing on it)
+    missed_writes: AtomicU64,
+    // writes reported as failed to zenoh, but that succeeded on this target (i.e. only present on it)
+    extra_writes: AtomicU64,
+    consecutive_failures: AtomicU64,
+    last_error: LastError,
+}
+
+impl TargetStats {
+    /// A target is healthy if its last request succeeded.
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.consecutive_failures.load(Ordering::Relaxed) == 0
+    }
+
+    pub(crate) fn on_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_failure<E: std::fmt::Display>(&self, e: &E) {
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        self.last_error.set(e);
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let missed_writes = self.missed_writes.load(Ordering::Relaxed);
+        let extra_writes = self.extra_writes.load(Ordering::Relaxed);
+        json!({
+            "healthy": self.is_healthy(),
+            "writes": self.writes.load(Ordering::Relaxed),
+            "failed_writes": self.failed_writes.load(Ordering::Relaxed),
+            "missed_writes": missed_writes,
+            "extra_writes": extra_writes,
+            "divergences": missed_writes + extra_writes,
+            "last_error": self.last_error.to_json(),
+        })
+    }
+}
+
+/// A mirror of the storage, on another InfluxDB instance.
+pub(crate) struct MirrorTarget {
+    pub(crate) url: String,
+    pub(crate) client: Client,
+}
+
+/// The targets a storage writes to: its primary database (on the volume's endpoints), plus its mirrors.
+/// The target #0 is always the primary.
+pub(crate) struct Mirrors {
+    ack: AckPolicy,
+    mirrors: Vec<MirrorTarget>,
+    // the stats of each target, the primary first
+    stats: Vec<TargetStats>,
+}
+
+impl Mirrors {
+    pub(crate) fn new(ack: AckPolicy, mirrors: Vec<MirrorTarget>) -> Self {
+        let stats = (0..=mirrors.len())
+            .map(|_| TargetStats::default())
+            .collect();
+        Mirrors {
+            ack,
+            mirrors,
+            stats,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.mirrors.is_empty()
+    }
+
+    pub(crate) fn mirrors(&self) -> &[MirrorTarget] {
+        &self.mirrors
+    }
+
+    pub(crate) fn stats(&self, target: usize) -> &TargetStats {
+        &self.stats[target]
+    }
+
+    /// The targets to read from: the healthy ones first, in configuration order.
+    pub(crate) fn read_order(&self) -> Vec<usize> {
+        let (mut order, unhealthy): (Vec<usize>, Vec<usize>) =
+            (0..self.stats.len()).partition(|i| self.stats[*i].is_healthy());
+        order.extend(unhealthy);
+        order
+    }
+
+    /// Apply the acknowledgement policy to the results of a write on each target (the primary first),
+    /// updating the targets stats. Returns the 1st error if the write is not acknowledged.
+    pub(crate) fn acknowledge<E: std::fmt::Display>(
+        &self,
+        results: Vec<Result<(), E>>,
+    ) -> Result<(), E> {
+        let succeeded = results.iter().filter(|r| r.is_ok()).count();
+        let acknowledged = succeeded >= self.ack.required(results.len());
+        let mut first_error = None;
+        for (stats, result) in self.stats.iter().zip(results) {
+            stats.writes.fetch_add(1, Ordering::Relaxed);
+            match result {
+                Ok(()) => {
+                    stats.on_success();
+                    if !acknowledged {
+                        stats.extra_writes.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => {
+                    stats.on_failure(&e);
+                    stats.failed_writes.fetch_add(1, Ordering::Relaxed);
+                    if acknowledged {
+                        stats.missed_writes.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                }
+            }
+        }
+        match first_error {
+            Some(e) if !acknowledged => Err(e),
+            Some(e) => {
+                warn!(
+                    "Write acknowledged by {} targets out of {}, but failed on others: {}",
+                    succeeded,
+                    self.stats.len(),
+                    e
+                );
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn to_json(&self, primary_url: &str) -> serde_json::Value {
+        let mut targets = vec![json!({
+            "url": primary_url,
+            "primary": true,
+            "stats": self.stats[0].to_json(),
+        })];
+        for (i, mirror) in self.mirrors.iter().enumerate() {
+            targets.push(json!({
+                "url": mirror.url,
+                "db": mirror.client.database_name(),
+                "primary": false,
+                "stats": self.stats[i + 1].to_json(),
+            }));
+        }
+        json!({
+            "ack": self.ack,
+            "targets": targets,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirrors(ack: AckPolicy, count: usize) -> Mirrors {
+        let mirrors = (0..count)
+            .map(|i| MirrorTarget {
+                url: format!("http://mirror{i}"),
+                client: Client::new(format!("http://mirror{i}"), "db"),
+            })
+            .collect();
+        Mirrors::new(ack, mirrors)
+    }
+
+    #[test]
+    fn quorum_rounding() {
+        // a strict majority
+        assert_eq!(AckPolicy::Quorum.required(1), 1);
+        assert_eq!(AckPolicy::Quorum.required(2), 2);
+        assert_eq!(AckPolicy::Quorum.required(3), 2);
+        assert_eq!(AckPolicy::Quorum.required(4), 3);
+        assert_eq!(AckPolicy::Quorum.required(5), 3);
+        assert_eq!(AckPolicy::All.required(3), 3);
+        assert_eq!(AckPolicy::Any.required(3), 1);
+    }
+
+    #[test]
+    fn acknowledge() {
+        let mirrors = mirrors(AckPolicy::Quorum, 2);
+        assert_eq!(
+            mirrors.acknowledge(vec![Ok(()), Err("down"), Ok(())]),
+            Ok(())
+        );
+        assert_eq!(mirrors.read_order(), [0, 2, 1]);
+        assert_eq!(
+            mirrors.acknowledge(vec![Ok(()), Err("down"), Err("refused")]),
+            Err("down")
+        );
+        assert_eq!(mirrors.read_order(), [0, 1, 2]);
+        let json = mirrors.to_json("http://primary");
+        assert_eq!(json["targets"][0]["stats"]["extra_writes"], 1);
+        assert_eq!(json["targets"][1]["stats"]["missed_writes"], 1);
+        assert_eq!(json["targets"][1]["stats"]["failed_writes"], 2);
+        assert_eq!(json["targets"][1]["stats"]["healthy"], false);
+        assert_eq!(
+            json["targets"][2]["stats"]["last_error"]["message"],
+            "refused"
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Retries of InfluxDB requests failing with transient errors, with exponential backoff and jitter.
+
+use crate::stats::{LatencyHistogram, StorageStats};
+use async_std::task;
+use async_trait::async_trait;
+use log::debug;
+use rand::Rng;
+use serde::Serialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use surf::middleware::{Middleware, Next};
+use surf::{Client, Request, Response, StatusCode};
+use zenoh::Result as ZResult;
+use zenoh_core::bail;
+
+/// The retry policy of the InfluxDB requests.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RetryPolicy {
+    // max number of retries after a first failure (0 to disable retries)
+    pub(crate) max_retries: u32,
+    #[serde(serialize_with = "crate::settings::serialize_duration")]
+    pub(crate) initial_backoff: Duration,
+    #[serde(serialize_with = "crate::settings::serialize_duration")]
+    pub(crate) max_backoff: Duration,
+    // max duration of an operation on a storage (PUT, DELETE or GET), including all its requests and their retries
+    #[serde(serialize_with = "crate::settings::serialize_duration")]
+    pub(crate) deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            deadline: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // The backoff before a retry: exponential, with a random jitter of up to half of it
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        backoff / 2 + jitter(backoff / 2)
+    }
+}
+
+fn jitter(max: Duration) -> Duration {
+    let max_us = max.as_micros() as u64;
+    if max_us == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_micros(rand::thread_rng().gen_range(0..max_us))
+}
+
+/// HTTP middleware failing the requests with a retryable HTTP status (5xx, 408 or 429),
+/// that the InfluxDB client would otherwise report as any database error.
+/// Being failed by the HTTP client, they're reported as connection errors, as are the timeouts.
+pub(crate) struct RetryableStatus;
+
+#[async_trait]
+impl Middleware for RetryableStatus {
+    async fn handle(&self, req: Request, client: Client, next: Next<'_>) -> surf::Result<Response> {
+        let mut res = next.run(req, client).await?;
+        let status = res.status();
+        if status.is_server_error()
+            || status == StatusCode::RequestTimeout
+            || status == StatusCode::TooManyRequests
+        {
+            let body = res.body_string().await.unwrap_or_default();
+            return Err(surf::Error::from_str(
+                status,
+                format!("HTTP status {status} : {}", body.trim()),
+            ));
+        }
+        Ok(res)
+    }
+}
+
+/// Returns true if the error is transient (i.e. a connection error, a timeout, or a retryable HTTP status
+/// as reported by RetryableStatus), and false if it's permanent (e.g. 4xx, parse error).
+pub(crate) fn is_transient(e: &influxdb::Error) -> bool {
+    matches!(e, influxdb::Error::ConnectionError { .. })
+}
+
+/// Run a storage operation (possibly made of several InfluxDB requests and their retries),
+/// failing it if it's not complete after a deadline, even if a request hangs.
+pub(crate) async fn with_deadline<T, F>(deadline: Duration, operation: F) -> ZResult<T>
+where
+    F: Future<Output = ZResult<T>>,
+{
+    match async_std::future::timeout(deadline, operation).await {
+        Ok(result) => result,
+        Err(_) => bail!(
+            "InfluxDB operation not complete after {} (see `retry.deadline`)",
+            humantime::format_duration(deadline)
+        ),
+    }
+}
+
+/// Run an InfluxDB request, retrying it on transient errors according to the policy.
+/// The latency of each attempt is recorded in `latency`, and each retry is counted in `stats`.
+pub(crate) async fn with_backoff<T, F, Fut>(
+    policy: &RetryPolicy,
+    stats: &StorageStats,
+    latency: &LatencyHistogram,
+    mut request: F,
+) -> Result<T, influxdb::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, influxdb::Error>>,
+{
+    let start = Instant::now();
+    let mut retry = 0;
+    loop {
+        let attempt_start = Instant::now();
+        let result = request().await;
+        latency.record(attempt_start.elapsed());
+        match result {
+            Err(e) if retry < policy.max_retries && is_transient(&e) => {
+                let backoff = policy.backoff(retry);
+                if start.elapsed() + backoff > policy.deadline {
+                    debug!("InfluxDB request failed, deadline reached: {}", e);
+                    return Err(e);
+                }
+                retry += 1;
+                StorageStats::incr(&stats.retries);
+                debug!(
+                    "InfluxDB request failed ({}), retry #{} in {:?}",
+                    e, retry, backoff
+                );
+                task::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_capping() {
+        let policy = RetryPolicy {
+            max_retries: 100,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            deadline: Duration::from_secs(30),
+        };
+        let first = policy.backoff(0);
+        assert!(first >= Duration::from_millis(50) && first < Duration::from_millis(100));
+        let second = policy.backoff(1);
+        assert!(second >= Duration::from_millis(100) && second < Duration::from_millis(200));
+        // capped, without overflowing
+        for retry in [4, 10, 31, 32, 100] {
+            let backoff = policy.backoff(retry);
+            assert!(backoff >= Duration::from_millis(500) && backoff < Duration::from_secs(1));
+        }
+        let policy = RetryPolicy {
+            initial_backoff: Duration::ZERO,
+            ..policy
+        };
+        assert_eq!(policy.backoff(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(is_transient(&influxdb::Error::ConnectionError {
+            error: "HTTP status 503 Service Unavailable : overloaded".into()
+        }));
+        assert!(!is_transient(&influxdb::Error::DatabaseError {
+            error: "database not found".into()
+        }));
+        assert!(!is_transient(&influxdb::Error::DeserializationError {
+            error: "invalid JSON".into()
+        }));
+        assert!(!is_transient(&influxdb::Error::AuthorizationError));
+    }
+}
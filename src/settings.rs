@@ -0,0 +1,1233 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Typed and validated configuration of the volume and of its storages.
+
+use crate::{
+    compression::Gzip,
+    deterministic_db_name,
+    endpoints::FailoverPolicy,
+    generate_db_name,
+    ingest::{Deadband, DeadbandRule, FutureDriftAction, QuotaSettings, RateLimitRule},
+    interceptor::{IncomingTransforms, KeyRewrite, OutputFormat},
+    mirror::AckPolicy,
+    retry::{RetryPolicy, RetryableStatus},
+    sweep::OrphanDbs,
+    NAMESPACE_SEPARATOR, PROP_BACKEND_COMPRESSION, PROP_BACKEND_CONNECT_TIMEOUT,
+    PROP_BACKEND_FAILOVER, PROP_BACKEND_HEALTH_CHECK_INTERVAL, PROP_BACKEND_HISTORY,
+    PROP_BACKEND_INCOMING, PROP_BACKEND_KEEP_ALIVE, PROP_BACKEND_MAX_CONNECTIONS,
+    PROP_BACKEND_METRICS_PORT, PROP_BACKEND_ORPHAN_DBS, PROP_BACKEND_ORPHAN_DBS_ALLOW,
+    PROP_BACKEND_ORPHAN_SWEEP_DELAY, PROP_BACKEND_OWNER, PROP_BACKEND_PASSWORD,
+    PROP_BACKEND_REQUEST_TIMEOUT, PROP_BACKEND_RETRY, PROP_BACKEND_URL, PROP_BACKEND_USERNAME,
+    PROP_RULE_KEY_EXPR, PROP_STORAGE_COLLISIONS, PROP_STORAGE_CREATE_DB, PROP_STORAGE_DB,
+    PROP_STORAGE_DB_NAMING, PROP_STORAGE_DEADBANDS, PROP_STORAGE_HEARTBEAT, PROP_STORAGE_HISTORY,
+    PROP_STORAGE_MAX_FUTURE_DRIFT, PROP_STORAGE_MIRRORS, PROP_STORAGE_MIRROR_ACK,
+    PROP_STORAGE_NAMESPACE, PROP_STORAGE_ON_CLOSURE, PROP_STORAGE_ON_COLLISION,
+    PROP_STORAGE_ON_FUTURE_DRIFT, PROP_STORAGE_OUTPUT_FORMAT, PROP_STORAGE_PASSWORD,
+    PROP_STORAGE_PRIVILEGE, PROP_STORAGE_QUOTAS, PROP_STORAGE_RATE_LIMITS, PROP_STORAGE_SHARDS,
+    PROP_STORAGE_SHARD_KEY_EXPR, PROP_STORAGE_SKIP_UNCHANGED, PROP_STORAGE_USERNAME,
+};
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::str::FromStr;
+use std::time::Duration;
+use surf::middleware::{Middleware, Next};
+use surf::{Client, Request, Response, StatusCode};
+use zenoh::prelude::{keyexpr, OwnedKeyExpr};
+use zenoh::Result as ZResult;
+use zenoh_backend_traits::config::{PrivacyGetResult, PrivacyTransparentGet};
+use zenoh_core::{bail, zerror};
+
+// Key of the section hiding credentials from the admin space
+const PRIVATE_KEY: &str = "private";
+// Key of the volume id in a storage's volume configuration
+const STORAGE_VOLUME_ID_KEY: &str = "id";
+
+const VOLUME_PROPERTIES: &[&str] = &[
+    PROP_BACKEND_URL,
+    PROP_BACKEND_FAILOVER,
+    PROP_BACKEND_METRICS_PORT,
+    PROP_BACKEND_HEALTH_CHECK_INTERVAL,
+    PROP_BACKEND_RETRY,
+    PROP_BACKEND_CONNECT_TIMEOUT,
+    PROP_BACKEND_REQUEST_TIMEOUT,
+    PROP_BACKEND_MAX_CONNECTIONS,
+    PROP_BACKEND_KEEP_ALIVE,
+    PROP_BACKEND_COMPRESSION,
+    PROP_BACKEND_INCOMING,
+    PROP_BACKEND_HISTORY,
+    PROP_BACKEND_ORPHAN_DBS,
+    PROP_BACKEND_ORPHAN_SWEEP_DELAY,
+    PROP_BACKEND_ORPHAN_DBS_ALLOW,
+    PROP_BACKEND_OWNER,
+    PROP_BACKEND_USERNAME,
+    PROP_BACKEND_PASSWORD,
+];
+
+const STORAGE_PROPERTIES: &[&str] = &[
+    STORAGE_VOLUME_ID_KEY,
+    PROP_STORAGE_DB,
+    PROP_STORAGE_CREATE_DB,
+    PROP_STORAGE_ON_CLOSURE,
+    PROP_STORAGE_PRIVILEGE,
+    PROP_STORAGE_MIRRORS,
+    PROP_STORAGE_MIRROR_ACK,
+    PROP_STORAGE_SHARDS,
+    PROP_STORAGE_OUTPUT_FORMAT,
+    PROP_STORAGE_HISTORY,
+    PROP_STORAGE_COLLISIONS,
+    PROP_STORAGE_ON_COLLISION,
+    PROP_STORAGE_MAX_FUTURE_DRIFT,
+    PROP_STORAGE_ON_FUTURE_DRIFT,
+    PROP_STORAGE_SKIP_UNCHANGED,
+    PROP_STORAGE_HEARTBEAT,
+    PROP_STORAGE_DEADBANDS,
+    PROP_STORAGE_RATE_LIMITS,
+    PROP_STORAGE_QUOTAS,
+    PROP_STORAGE_NAMESPACE,
+    PROP_STORAGE_DB_NAMING,
+    PROP_STORAGE_USERNAME,
+    PROP_STORAGE_PASSWORD,
+];
+
+const REMOTE_DB_PROPERTIES: &[&str] = &[
+    PROP_BACKEND_URL,
+    PROP_STORAGE_DB,
+    PROP_BACKEND_USERNAME,
+    PROP_BACKEND_PASSWORD,
+];
+
+const SHARD_PROPERTIES: &[&str] = &[
+    PROP_STORAGE_SHARD_KEY_EXPR,
+    PROP_BACKEND_URL,
+    PROP_STORAGE_DB,
+    PROP_BACKEND_USERNAME,
+    PROP_BACKEND_PASSWORD,
+];
+
+const INCOMING_PROPERTIES: &[&str] = &[
+    "key_rewrites",
+    "allowed_encodings",
+    "json_fields",
+    "max_payload_size",
+];
+
+const DEADBAND_PROPERTIES: &[&str] = &[PROP_RULE_KEY_EXPR, "absolute", "percent", "max_interval"];
+
+const RATE_LIMIT_PROPERTIES: &[&str] = &[PROP_RULE_KEY_EXPR, "min_interval", "mode"];
+
+const QUOTA_PROPERTIES: &[&str] = &["max_keys", "max_points_per_key", "max_bytes_per_payload"];
+
+const KEY_REWRITE_PROPERTIES: &[&str] = &["from", "to"];
+
+const RETRY_PROPERTIES: &[&str] = &["max_retries", "initial_backoff", "max_backoff", "deadline"];
+
+const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_ORPHAN_SWEEP_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_CONNECTIONS: usize = 50;
+
+const CREDENTIALS_PROPERTIES: &[&str] = &[PROP_BACKEND_USERNAME, PROP_BACKEND_PASSWORD];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OnClosure {
+    DropDb,
+    DropSeries,
+    #[default]
+    DoNothing,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DbNaming {
+    // a random name, i.e. a new database on each start
+    #[default]
+    Random,
+    // a name derived from the storage's name and key expression
+    Deterministic,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Privilege {
+    Read,
+    Write,
+    #[default]
+    All,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HistoryMode {
+    // all the values are kept
+    #[default]
+    All,
+    // only the latest value of each key is kept
+    Latest,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Collisions {
+    // points are identified by their key, source and time only (a sample can overwrite another one from the same source with the same time)
+    #[default]
+    None,
+    // the time of a point is shifted by 1ns until it doesn't collide with another sample's point
+    UniqueTime,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OnCollision {
+    // the new sample overwrites the stored one
+    #[default]
+    Overwrite,
+    // the stored sample is kept
+    KeepExisting,
+    // the sample with the greatest timestamp (time, then source ID) is kept
+    KeepGreatest,
+}
+
+impl Privilege {
+    // The privilege as used in a GRANT statement
+    pub(crate) fn as_influxql(&self) -> &'static str {
+        match self {
+            Privilege::Read => "READ",
+            Privilege::Write => "WRITE",
+            Privilege::All => "ALL",
+        }
+    }
+
+    // Returns true if this privilege is covered by a privilege as returned by SHOW GRANTS
+    pub(crate) fn is_covered_by(&self, granted: &str) -> bool {
+        match granted {
+            "ALL PRIVILEGES" => true,
+            "READ" => *self == Privilege::Read,
+            "WRITE" => *self == Privilege::Write,
+            _ => false,
+        }
+    }
+}
+
+/// The settings of an InfluxDb volume, normalized from its configuration.
+#[derive(Debug, Serialize)]
+pub(crate) struct VolumeSettings {
+    // the InfluxDB endpoints (several for failover)
+    pub(crate) urls: Vec<String>,
+    pub(crate) failover: FailoverPolicy,
+    // local port serving the metrics over HTTP ("metrics" feature only)
+    pub(crate) metrics_port: Option<u16>,
+    // period of the probe checking InfluxDB is reachable
+    #[serde(serialize_with = "serialize_duration")]
+    pub(crate) health_check_interval: Duration,
+    // retry policy of the requests failing with transient errors
+    pub(crate) retry: RetryPolicy,
+    // HTTP settings of the InfluxDB clients
+    pub(crate) http: HttpSettings,
+    // the history kept by the storages (and advertised in the volume's capability)
+    pub(crate) history: HistoryMode,
+    // the sweep of the orphan generated databases, and its delay after the volume's start
+    pub(crate) orphan_dbs: OrphanDbs,
+    #[serde(serialize_with = "serialize_duration")]
+    pub(crate) orphan_sweep_delay: Duration,
+    // the orphan databases dropped by the sweep although not marked with the volume's owner
+    pub(crate) orphan_dbs_allow: Vec<String>,
+    // the owner marked in the databases generated by the volume's storages, if any
+    pub(crate) owner: Option<String>,
+    // transforms of the samples before they're stored
+    // Note: exposed in admin status with their counters, under "incoming"
+    #[serde(skip)]
+    pub(crate) incoming: IncomingTransforms,
+    // Note: never re-exposed in admin status
+    #[serde(skip)]
+    pub(crate) credentials: Option<(String, String)>,
+}
+
+impl VolumeSettings {
+    pub(crate) fn parse(config: &Map<String, JsonValue>) -> ZResult<VolumeSettings> {
+        let mut parser = SettingsParser::new("InfluxDb volume".into(), config, VOLUME_PROPERTIES);
+        let urls = match parser.required::<OneOrMany<String>>(PROP_BACKEND_URL) {
+            Some(OneOrMany::One(url)) => Some(vec![url]),
+            Some(OneOrMany::Many(urls)) if urls.is_empty() => {
+                parser.error(format!("`{PROP_BACKEND_URL}` must not be an empty list"));
+                None
+            }
+            Some(OneOrMany::Many(urls)) => Some(urls),
+            None => None,
+        };
+        let failover = parser.optional(PROP_BACKEND_FAILOVER).unwrap_or_default();
+        let metrics_port = parser.optional::<u16>(PROP_BACKEND_METRICS_PORT);
+        if cfg!(not(feature = "metrics")) && metrics_port.is_some() {
+            parser.error(format!(
+                "`{PROP_BACKEND_METRICS_PORT}` requires the backend to be built with the \"metrics\" feature"
+            ));
+        }
+        let health_check_interval = parser
+            .duration(PROP_BACKEND_HEALTH_CHECK_INTERVAL)
+            .unwrap_or(DEFAULT_HEALTH_CHECK_INTERVAL);
+        let retry = parser
+            .section(PROP_BACKEND_RETRY, RETRY_PROPERTIES, |p| {
+                let default = RetryPolicy::default();
+                RetryPolicy {
+                    max_retries: p.optional("max_retries").unwrap_or(default.max_retries),
+                    initial_backoff: p
+                        .duration("initial_backoff")
+                        .unwrap_or(default.initial_backoff),
+                    max_backoff: p.duration("max_backoff").unwrap_or(default.max_backoff),
+                    deadline: p.duration("deadline").unwrap_or(default.deadline),
+                }
+            })
+            .unwrap_or_default();
+        let max_connections_per_host = match parser.optional::<usize>(PROP_BACKEND_MAX_CONNECTIONS)
+        {
+            Some(0) => {
+                parser.error(format!("`{PROP_BACKEND_MAX_CONNECTIONS}` must not be 0"));
+                DEFAULT_MAX_CONNECTIONS
+            }
+            Some(max) => max,
+            None => DEFAULT_MAX_CONNECTIONS,
+        };
+        let http = HttpSettings {
+            connect_timeout: parser
+                .duration(PROP_BACKEND_CONNECT_TIMEOUT)
+                .unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            request_timeout: parser
+                .duration(PROP_BACKEND_REQUEST_TIMEOUT)
+                .unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+            max_connections_per_host,
+            keep_alive: parser.optional(PROP_BACKEND_KEEP_ALIVE).unwrap_or(true),
+            compression: parser
+                .optional(PROP_BACKEND_COMPRESSION)
+                .unwrap_or_default(),
+        };
+        let incoming = parser
+            .section(PROP_BACKEND_INCOMING, INCOMING_PROPERTIES, |p| {
+                IncomingTransforms {
+                    key_rewrites: p.sections("key_rewrites", KEY_REWRITE_PROPERTIES, |p| {
+                        let from = p.key_prefix("from");
+                        let to = p.key_prefix("to");
+                        Some(KeyRewrite {
+                            from: from?,
+                            to: to?,
+                        })
+                    }),
+                    allowed_encodings: p.optional("allowed_encodings"),
+                    json_fields: p.optional("json_fields"),
+                    max_payload_size: p.optional("max_payload_size"),
+                }
+            })
+            .unwrap_or_default();
+        let history = parser.optional(PROP_BACKEND_HISTORY).unwrap_or_default();
+        let orphan_dbs = parser
+            .optional::<OrphanDbs>(PROP_BACKEND_ORPHAN_DBS)
+            .unwrap_or_default();
+        let orphan_sweep_delay = parser
+            .duration(PROP_BACKEND_ORPHAN_SWEEP_DELAY)
+            .unwrap_or(DEFAULT_ORPHAN_SWEEP_DELAY);
+        let orphan_dbs_allow = parser
+            .optional::<Vec<String>>(PROP_BACKEND_ORPHAN_DBS_ALLOW)
+            .unwrap_or_default();
+        let owner = parser.optional::<String>(PROP_BACKEND_OWNER);
+        if owner.as_deref() == Some("") {
+            parser.error(format!("`{PROP_BACKEND_OWNER}` must not be empty"));
+        }
+        if !orphan_dbs_allow.is_empty() && orphan_dbs != OrphanDbs::Drop {
+            parser.error(format!(
+                "`{PROP_BACKEND_ORPHAN_DBS_ALLOW}` requires `{PROP_BACKEND_ORPHAN_DBS}` to be \"drop\""
+            ));
+        }
+        if orphan_dbs == OrphanDbs::Drop && owner.is_none() && orphan_dbs_allow.is_empty() {
+            parser.error(format!(
+                "`{PROP_BACKEND_ORPHAN_DBS}` \"drop\" requires `{PROP_BACKEND_OWNER}` or `{PROP_BACKEND_ORPHAN_DBS_ALLOW}`, to only drop the databases of this volume"
+            ));
+        }
+        let credentials = parser.credentials();
+        parser.finish()?;
+        Ok(VolumeSettings {
+            // can't be None if no error was reported
+            urls: urls.unwrap(),
+            failover,
+            metrics_port,
+            health_check_interval,
+            retry,
+            http,
+            history,
+            orphan_dbs,
+            orphan_sweep_delay,
+            orphan_dbs_allow,
+            owner,
+            incoming,
+            credentials,
+        })
+    }
+}
+
+// A property that can be a single value or a list of values
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HttpCompression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// The HTTP settings of the InfluxDB clients.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct HttpSettings {
+    // timeout of the connectivity checks (/ping), and of the connection establishment of any request
+    #[serde(serialize_with = "serialize_duration")]
+    pub(crate) connect_timeout: Duration,
+    // timeout of any request, once connected
+    #[serde(serialize_with = "serialize_duration")]
+    pub(crate) request_timeout: Duration,
+    // max number of connections in the pool, per InfluxDB host
+    pub(crate) max_connections_per_host: usize,
+    pub(crate) keep_alive: bool,
+    pub(crate) compression: HttpCompression,
+}
+
+impl HttpSettings {
+    /// Create the HTTP client to be shared by the InfluxDB clients.
+    pub(crate) fn http_client(&self) -> ZResult<surf::Client> {
+        let client: surf::Client = surf::Config::new()
+            .set_timeout(Some(self.request_timeout))
+            .set_http_keep_alive(self.keep_alive)
+            .set_max_connections_per_host(self.max_connections_per_host)
+            .try_into()
+            .map_err(|e| zerror!("Failed to create HTTP client for InfluxDB : {}", e))?;
+        let client = client
+            .with(ConnectTimeout(self.connect_timeout + self.request_timeout))
+            .with(RetryableStatus);
+        Ok(match self.compression {
+            HttpCompression::None => client,
+            HttpCompression::Gzip => client.with(Gzip),
+        })
+    }
+}
+
+/// HTTP middleware bounding the whole requests, including the connection establishment
+/// (the HTTP client's timeout only bounds the requests once connected, not the connections to InfluxDB).
+struct ConnectTimeout(Duration);
+
+#[async_trait]
+impl Middleware for ConnectTimeout {
+    async fn handle(&self, req: Request, client: Client, next: Next<'_>) -> surf::Result<Response> {
+        match async_std::future::timeout(self.0, next.run(req, client)).await {
+            Ok(result) => result,
+            Err(_) => Err(surf::Error::from_str(
+                StatusCode::RequestTimeout,
+                format!("no response (nor connection) after {:?}", self.0),
+            )),
+        }
+    }
+}
+
+/// The settings of a storage, normalized from the `volume` section of its configuration.
+#[derive(Debug, Serialize)]
+pub(crate) struct StorageSettings {
+    // the database name, possibly generated
+    pub(crate) db: String,
+    pub(crate) create_db: bool,
+    // if the database name is randomly generated (thus the database marked with the volume's owner).
+    // Note: the deterministic databases are never marked, to be kept across a storage's re-creation
+    pub(crate) random_db: bool,
+    pub(crate) on_closure: OnClosure,
+    pub(crate) privilege: Privilege,
+    // other InfluxDB instances the writes are mirrored to
+    pub(crate) mirrors: Vec<RemoteDbSettings>,
+    pub(crate) mirror_ack: AckPolicy,
+    // the shards, other InfluxDB instances storing some of the keys
+    pub(crate) shards: Vec<ShardSettings>,
+    // the conversion of the values on replies
+    pub(crate) output_format: OutputFormat,
+    // the history kept, the volume's one
+    pub(crate) history: HistoryMode,
+    // how points collisions are avoided, or resolved
+    pub(crate) collisions: Collisions,
+    pub(crate) on_collision: OnCollision,
+    // the max drift in the future of the samples timestamps (no guard if None)
+    #[serde(serialize_with = "serialize_opt_duration")]
+    pub(crate) max_future_drift: Option<Duration>,
+    pub(crate) on_future_drift: FutureDriftAction,
+    // skip the samples with the same value than the last one of their key
+    pub(crate) skip_unchanged: bool,
+    // the max period without write of an unchanged key (never if None)
+    #[serde(serialize_with = "serialize_opt_duration")]
+    pub(crate) heartbeat: Option<Duration>,
+    // the deadbands of the numeric values, by key expression
+    pub(crate) deadbands: Vec<DeadbandRule>,
+    // the min intervals between the samples of a key, by key expression
+    pub(crate) rate_limits: Vec<RateLimitRule>,
+    pub(crate) quotas: Option<QuotaSettings>,
+    // the namespace of the storage's measurements in its database (if shared with other storages)
+    pub(crate) namespace: Option<String>,
+    // how the database name is generated, if not specified
+    pub(crate) db_naming: DbNaming,
+    // Note: only the username is re-exposed in admin status
+    pub(crate) username: Option<String>,
+    #[serde(skip)]
+    pub(crate) password: Option<String>,
+}
+
+/// The settings of a database on another InfluxDB instance than the volume's (a mirror or a shard).
+#[derive(Debug, Serialize)]
+pub(crate) struct RemoteDbSettings {
+    pub(crate) url: String,
+    // the database name, same as the storage's one by default
+    pub(crate) db: String,
+    // Note: never re-exposed in admin status
+    #[serde(skip)]
+    pub(crate) credentials: Option<(String, String)>,
+}
+
+/// The settings of a storage's shard.
+#[derive(Debug, Serialize)]
+pub(crate) struct ShardSettings {
+    // the keys stored in this shard (including the storage's `strip_prefix`)
+    #[serde(serialize_with = "serialize_key_expr")]
+    pub(crate) key_expr: OwnedKeyExpr,
+    #[serde(flatten)]
+    pub(crate) remote: RemoteDbSettings,
+}
+
+impl StorageSettings {
+    pub(crate) fn parse(
+        storage_name: &str,
+        key_expr: &keyexpr,
+        volume_cfg: &Map<String, JsonValue>,
+        volume_history: HistoryMode,
+    ) -> ZResult<StorageSettings> {
+        let mut parser = SettingsParser::new(
+            format!("storage `{storage_name}`"),
+            volume_cfg,
+            STORAGE_PROPERTIES,
+        );
+        let db = parser.optional::<String>(PROP_STORAGE_DB);
+        let create_db = parser
+            .optional::<bool>(PROP_STORAGE_CREATE_DB)
+            .unwrap_or(false);
+        let on_closure: OnClosure = parser.optional(PROP_STORAGE_ON_CLOSURE).unwrap_or_default();
+        let privilege: Privilege = parser.optional(PROP_STORAGE_PRIVILEGE).unwrap_or_default();
+        let mirrors = parser.sections(PROP_STORAGE_MIRRORS, REMOTE_DB_PROPERTIES, |p| {
+            p.remote_db()
+        });
+        let mirror_ack = parser.optional(PROP_STORAGE_MIRROR_ACK).unwrap_or_default();
+        let shards = parser.sections(PROP_STORAGE_SHARDS, SHARD_PROPERTIES, |p| {
+            let key_expr = p.key_expr(PROP_STORAGE_SHARD_KEY_EXPR);
+            let remote = p.remote_db();
+            Some((key_expr?, remote?))
+        });
+        // the keys routed to a shard would bypass the mirrors
+        if !mirrors.is_empty() && !shards.is_empty() {
+            parser.error(format!(
+                "`{PROP_STORAGE_MIRRORS}` and `{PROP_STORAGE_SHARDS}` can't be used together"
+            ));
+        }
+        let output_format = parser
+            .optional(PROP_STORAGE_OUTPUT_FORMAT)
+            .unwrap_or_default();
+        // the history is advertised in the volume's capability, thus the same for all its storages
+        let history = volume_history;
+        if volume_cfg.contains_key(PROP_STORAGE_HISTORY) {
+            parser.error(format!(
+                "`{PROP_STORAGE_HISTORY}` can only be set on the volume, being advertised in its capability"
+            ));
+        }
+        let collisions = parser.optional(PROP_STORAGE_COLLISIONS).unwrap_or_default();
+        let on_collision = parser
+            .optional(PROP_STORAGE_ON_COLLISION)
+            .unwrap_or_default();
+        let max_future_drift = parser.duration(PROP_STORAGE_MAX_FUTURE_DRIFT);
+        let on_future_drift = parser
+            .optional(PROP_STORAGE_ON_FUTURE_DRIFT)
+            .unwrap_or_default();
+        let skip_unchanged = parser
+            .optional(PROP_STORAGE_SKIP_UNCHANGED)
+            .unwrap_or(false);
+        let heartbeat = parser.duration(PROP_STORAGE_HEARTBEAT);
+        let deadbands = parser.sections(PROP_STORAGE_DEADBANDS, DEADBAND_PROPERTIES, |p| {
+            let key_expr = p.key_expr(PROP_RULE_KEY_EXPR);
+            let deadband = p.deadband();
+            let max_interval = p.duration("max_interval");
+            Some(DeadbandRule {
+                key_expr: key_expr?,
+                deadband: deadband?,
+                max_interval,
+            })
+        });
+        let rate_limits = parser.sections(PROP_STORAGE_RATE_LIMITS, RATE_LIMIT_PROPERTIES, |p| {
+            let key_expr = p.key_expr(PROP_RULE_KEY_EXPR);
+            let min_interval = p.duration("min_interval");
+            if min_interval.is_none() {
+                p.error("missing mandatory property `min_interval`".into());
+            }
+            let mode = p.optional("mode").unwrap_or_default();
+            Some(RateLimitRule {
+                key_expr: key_expr?,
+                min_interval: min_interval?,
+                mode,
+            })
+        });
+        let quotas = parser.section(PROP_STORAGE_QUOTAS, QUOTA_PROPERTIES, |p| QuotaSettings {
+            max_keys: p.optional("max_keys"),
+            max_points_per_key: p.optional("max_points_per_key"),
+            max_bytes_per_payload: p.optional("max_bytes_per_payload"),
+        });
+        let db_naming = parser.optional(PROP_STORAGE_DB_NAMING).unwrap_or_default();
+        let namespace = parser.optional::<String>(PROP_STORAGE_NAMESPACE);
+        if let Some(namespace) = &namespace {
+            if namespace.is_empty() || namespace.contains(NAMESPACE_SEPARATOR) {
+                parser.error(format!(
+                    "invalid value \"{namespace}\" for `{PROP_STORAGE_NAMESPACE}`: must be non-empty and without '{NAMESPACE_SEPARATOR}'"
+                ));
+            }
+            if on_closure == OnClosure::DropDb {
+                parser.error(format!(
+                    "`{PROP_STORAGE_ON_CLOSURE}` can't be \"drop_db\" with a `{PROP_STORAGE_NAMESPACE}` (the database is shared)"
+                ));
+            }
+        }
+        // a write-only storage can't read its database, as needed by some options
+        if privilege == Privilege::Write {
+            let mut reading = Vec::new();
+            if history == HistoryMode::Latest {
+                reading.push(format!("the volume's `{PROP_STORAGE_HISTORY}` \"latest\""));
+            }
+            if collisions != Collisions::None || on_collision != OnCollision::Overwrite {
+                reading.push(format!(
+                    "`{PROP_STORAGE_COLLISIONS}` or `{PROP_STORAGE_ON_COLLISION}`"
+                ));
+            }
+            if skip_unchanged {
+                reading.push(format!("`{PROP_STORAGE_SKIP_UNCHANGED}`"));
+            }
+            if let Some(quotas) = &quotas {
+                if quotas.max_keys.is_some() || quotas.max_points_per_key.is_some() {
+                    reading.push(format!("`{PROP_STORAGE_QUOTAS}` on keys or points"));
+                }
+            }
+            for option in reading {
+                parser.error(format!(
+                    "`{PROP_STORAGE_PRIVILEGE}` \"write\" can't be used with {option}, which requires to read the database"
+                ));
+            }
+        }
+        if heartbeat.is_some() && !skip_unchanged {
+            parser.error(format!(
+                "`{PROP_STORAGE_HEARTBEAT}` requires `{PROP_STORAGE_SKIP_UNCHANGED}` to be true"
+            ));
+        }
+        let credentials = parser.credentials();
+        parser.finish()?;
+
+        // if no database name is specified, generate one and always create it
+        let random_db = db.is_none() && db_naming == DbNaming::Random;
+        let (db, create_db) = match db {
+            Some(db) => (db, create_db),
+            None => match db_naming {
+                DbNaming::Random => (generate_db_name(), true),
+                DbNaming::Deterministic => (deterministic_db_name(storage_name, key_expr), true),
+            },
+        };
+        let with_db = |(mut remote, remote_db): (RemoteDbSettings, Option<String>)| {
+            remote.db = remote_db.unwrap_or_else(|| db.clone());
+            remote
+        };
+        let mirrors = mirrors.into_iter().map(with_db).collect();
+        let shards = shards
+            .into_iter()
+            .map(|(key_expr, remote)| ShardSettings {
+                key_expr,
+                remote: with_db(remote),
+            })
+            .collect();
+        let (username, password) = match credentials {
+            Some((username, password)) => (Some(username), Some(password)),
+            None => (None, None),
+        };
+        Ok(StorageSettings {
+            db,
+            create_db,
+            random_db,
+            on_closure,
+            privilege,
+            mirrors,
+            mirror_ack,
+            shards,
+            output_format,
+            history,
+            collisions,
+            on_collision,
+            max_future_drift,
+            on_future_drift,
+            skip_unchanged,
+            heartbeat,
+            deadbands,
+            rate_limits,
+            quotas,
+            namespace,
+            db_naming,
+            username,
+            password,
+        })
+    }
+}
+
+// Parses the properties of a configuration, collecting all the errors to report them at once.
+struct SettingsParser<'a> {
+    what: String,
+    config: &'a Map<String, JsonValue>,
+    known: &'static [&'static str],
+    errors: Vec<String>,
+}
+
+impl<'a> SettingsParser<'a> {
+    fn new(
+        what: String,
+        config: &'a Map<String, JsonValue>,
+        known: &'static [&'static str],
+    ) -> Self {
+        SettingsParser {
+            what,
+            config,
+            known,
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+
+    fn optional<T: DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        let value = self.config.get(key)?;
+        match serde_json::from_value(value.clone()) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.errors
+                    .push(format!("invalid value {value} for `{key}`: {e}"));
+                None
+            }
+        }
+    }
+
+    // Parse a section (i.e. a sub-object) of the configuration, reporting its errors prefixed with its key
+    fn section<T, F>(&mut self, key: &str, known: &'static [&'static str], parse: F) -> Option<T>
+    where
+        F: FnOnce(&mut SettingsParser) -> T,
+    {
+        let section = match self.config.get(key)? {
+            JsonValue::Object(section) => section,
+            _ => {
+                self.errors.push(format!("`{key}` must be an object"));
+                return None;
+            }
+        };
+        self.parse_section(key, section, known, parse)
+    }
+
+    fn parse_section<T, F>(
+        &mut self,
+        what: &str,
+        section: &Map<String, JsonValue>,
+        known: &'static [&'static str],
+        parse: F,
+    ) -> Option<T>
+    where
+        F: FnOnce(&mut SettingsParser) -> T,
+    {
+        let mut parser = SettingsParser::new(what.to_string(), section, known);
+        let result = parse(&mut parser);
+        parser.check_unknown_keys();
+        if parser.errors.is_empty() {
+            Some(result)
+        } else {
+            self.errors.extend(
+                parser
+                    .errors
+                    .into_iter()
+                    .map(|e| format!("in `{what}`: {e}")),
+            );
+            None
+        }
+    }
+
+    // Get a key expression, expressed as a string
+    fn key_expr(&mut self, key: &str) -> Option<OwnedKeyExpr> {
+        let value: String = self.required(key)?;
+        match OwnedKeyExpr::from_str(&value) {
+            Ok(ke) => Some(ke),
+            Err(e) => {
+                self.errors.push(format!(
+                    "invalid key expression \"{value}\" for `{key}`: {e}"
+                ));
+                None
+            }
+        }
+    }
+
+    // Get a key prefix: a key expression without wildcards
+    fn key_prefix(&mut self, key: &str) -> Option<String> {
+        let ke = self.key_expr(key)?;
+        if ke.as_str().contains('*') {
+            self.errors.push(format!(
+                "invalid key prefix \"{ke}\" for `{key}`: must not contain wildcards"
+            ));
+            return None;
+        }
+        Some(ke.to_string())
+    }
+
+    // The threshold of a deadband: either "absolute" or "percent"
+    fn deadband(&mut self) -> Option<Deadband> {
+        let absolute = self.optional::<f64>("absolute");
+        let percent = self.optional::<f64>("percent");
+        let deadband = match (absolute, percent) {
+            (Some(absolute), None) => Deadband::Absolute(absolute),
+            (None, Some(percent)) => Deadband::Percent(percent),
+            _ => {
+                self.errors
+                    .push("exactly one of `absolute` or `percent` must be set".into());
+                return None;
+            }
+        };
+        match deadband {
+            Deadband::Absolute(threshold) | Deadband::Percent(threshold)
+                if !(threshold.is_finite() && threshold >= 0.0) =>
+            {
+                self.errors.push(format!(
+                    "invalid deadband threshold {threshold}: must be a positive number"
+                ));
+                None
+            }
+            deadband => Some(deadband),
+        }
+    }
+
+    // Get a duration, expressed as a string in humantime format (e.g. "10s", "1m 30s", "500ms")
+    fn duration(&mut self, key: &str) -> Option<Duration> {
+        let value: String = self.optional(key)?;
+        match humantime::parse_duration(&value) {
+            Ok(d) if !d.is_zero() => Some(d),
+            Ok(_) => {
+                self.errors.push(format!(
+                    "invalid value \"{value}\" for `{key}`: must not be 0"
+                ));
+                None
+            }
+            Err(e) => {
+                self.errors
+                    .push(format!("invalid duration \"{value}\" for `{key}`: {e}"));
+                None
+            }
+        }
+    }
+
+    fn required<T: DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        if !self.config.contains_key(key) {
+            self.errors
+                .push(format!("missing mandatory property `{key}`"));
+            return None;
+        }
+        self.optional(key)
+    }
+
+    // Get a string property that could be hidden behind a `private` gate
+    fn private(&mut self, key: &str) -> Option<String> {
+        match self.config.get_private(key) {
+            PrivacyGetResult::NotFound => None,
+            PrivacyGetResult::Private(JsonValue::String(v)) => Some(v.clone()),
+            PrivacyGetResult::Public(JsonValue::String(v)) => {
+                log::warn!(
+                    r#"Value "{}" is given for `{}` publicly (i.e. is visible by anyone who can fetch the router configuration). You may want to replace `{}: "{}"` with `private: {{{}: "{}"}}`"#,
+                    v,
+                    key,
+                    key,
+                    v,
+                    key,
+                    v
+                );
+                Some(v.clone())
+            }
+            PrivacyGetResult::Both {
+                public: JsonValue::String(public),
+                private: JsonValue::String(private),
+            } => {
+                log::warn!(
+                    r#"Value "{}" is given for `{}` publicly, but a private value also exists. The private value will be used, but the public value, which is {} the same as the private one, will still be visible in configurations."#,
+                    public,
+                    key,
+                    if public == private { "" } else { "not " }
+                );
+                Some(private.clone())
+            }
+            _ => {
+                self.errors
+                    .push(format!("optional property `{key}` must be a string"));
+                None
+            }
+        }
+    }
+
+    // Parse a list of sections, reporting their errors prefixed with their key and index
+    fn sections<T, F>(&mut self, key: &str, known: &'static [&'static str], parse: F) -> Vec<T>
+    where
+        F: Fn(&mut SettingsParser) -> Option<T>,
+    {
+        let sections = match self.optional::<Vec<Map<String, JsonValue>>>(key) {
+            Some(sections) => sections,
+            None => return Vec::new(),
+        };
+        sections
+            .iter()
+            .enumerate()
+            .filter_map(|(i, section)| {
+                self.parse_section(&format!("{key}[{i}]"), section, known, &parse)
+                    .flatten()
+            })
+            .collect()
+    }
+
+    // Get the properties of a database on another InfluxDB instance (a mirror or a shard).
+    // The database name defaults to the storage's one, and is set afterwards.
+    fn remote_db(&mut self) -> Option<(RemoteDbSettings, Option<String>)> {
+        let url = self.required::<String>(PROP_BACKEND_URL);
+        let db = self.optional::<String>(PROP_STORAGE_DB);
+        let credentials = self.credentials();
+        Some((
+            RemoteDbSettings {
+                url: url?,
+                db: String::new(),
+                credentials,
+            },
+            db,
+        ))
+    }
+
+    fn credentials(&mut self) -> Option<(String, String)> {
+        match (
+            self.private(PROP_BACKEND_USERNAME),
+            self.private(PROP_BACKEND_PASSWORD),
+        ) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => {
+                self.errors.push(format!(
+                    "optional properties `{PROP_BACKEND_USERNAME}` and `{PROP_BACKEND_PASSWORD}` must coexist"
+                ));
+                None
+            }
+        }
+    }
+
+    fn check_unknown_keys(&mut self) {
+        for key in self.config.keys() {
+            // keys starting with "__" are internal to zenoh
+            if key == PRIVATE_KEY || key.starts_with("__") || self.known.contains(&key.as_str()) {
+                continue;
+            }
+            self.errors
+                .push(unknown_property_error(key, "", self.known));
+        }
+        if let Some(private) = self.config.get(PRIVATE_KEY) {
+            match private.as_object() {
+                Some(private) => {
+                    for key in private.keys() {
+                        if !CREDENTIALS_PROPERTIES.contains(&key.as_str()) {
+                            self.errors.push(unknown_property_error(
+                                key,
+                                "private.",
+                                CREDENTIALS_PROPERTIES,
+                            ));
+                        }
+                    }
+                }
+                None => self
+                    .errors
+                    .push(format!("`{PRIVATE_KEY}` must be an object")),
+            }
+        }
+    }
+
+    fn finish(mut self) -> ZResult<()> {
+        self.check_unknown_keys();
+        if !self.errors.is_empty() {
+            bail!(
+                "Invalid configuration for {}:\n - {}",
+                self.what,
+                self.errors.join("\n - ")
+            )
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn serialize_duration<S: serde::Serializer>(
+    d: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&humantime::format_duration(*d).to_string())
+}
+
+pub(crate) fn serialize_opt_duration<S: serde::Serializer>(
+    d: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => serialize_duration(d, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub(crate) fn serialize_key_expr<S: serde::Serializer>(
+    ke: &OwnedKeyExpr,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(ke.as_str())
+}
+
+fn unknown_property_error(key: &str, prefix: &str, known: &[&str]) -> String {
+    match known
+        .iter()
+        .map(|k| (levenshtein(key, k), k))
+        .filter(|(d, _)| *d <= 2)
+        .min()
+    {
+        Some((_, suggestion)) => {
+            format!("unknown property `{prefix}{key}` (did you mean `{prefix}{suggestion}`?)")
+        }
+        None => format!(
+            "unknown property `{prefix}{key}` (expected one of: {})",
+            known.join(", ")
+        ),
+    }
+}
+
+// Edit distance between 2 strings, used to suggest a property name on typos
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const KNOWN: &[&str] = &["url", "db", "create_db", "timeout"];
+
+    fn config(value: JsonValue) -> Map<String, JsonValue> {
+        match value {
+            JsonValue::Object(config) => config,
+            _ => panic!("not an object: {value}"),
+        }
+    }
+
+    // Parse a configuration with some properties, returning the reported errors
+    fn errors<F>(value: JsonValue, parse: F) -> Vec<String>
+    where
+        F: FnOnce(&mut SettingsParser),
+    {
+        let config = config(value);
+        let mut parser = SettingsParser::new("test".into(), &config, KNOWN);
+        parse(&mut parser);
+        parser.check_unknown_keys();
+        parser.errors
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(
+            errors(json!({"url": "http://localhost:8086", "foo": 1}), |_| ()),
+            vec!["unknown property `foo` (expected one of: url, db, create_db, timeout)"]
+        );
+        // internal and private keys are not reported
+        assert!(errors(
+            json!({"__path__": "x", "private": {"username": "u", "password": "p"}}),
+            |_| ()
+        )
+        .is_empty());
+        assert_eq!(
+            errors(json!({"private": "x"}), |_| ()),
+            vec!["`private` must be an object"]
+        );
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(
+            errors(json!({"ulr": "http://localhost:8086"}), |_| ()),
+            vec!["unknown property `ulr` (did you mean `url`?)"]
+        );
+        assert_eq!(
+            errors(json!({"create-db": true}), |_| ()),
+            vec!["unknown property `create-db` (did you mean `create_db`?)"]
+        );
+        assert_eq!(
+            errors(json!({"private": {"pasword": "p"}}), |_| ()),
+            vec!["unknown property `private.pasword` (did you mean `private.password`?)"]
+        );
+        assert_eq!(levenshtein("", "url"), 3);
+        assert_eq!(levenshtein("url", "url"), 0);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn type_errors() {
+        let errors = errors(json!({"create_db": "yes", "db": 1}), |p| {
+            assert_eq!(p.optional::<bool>("create_db"), None);
+            assert_eq!(p.optional::<String>("db"), None);
+            assert_eq!(p.optional::<String>("url"), None);
+        });
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("invalid value \"yes\" for `create_db`"));
+        assert!(errors[1].starts_with("invalid value 1 for `db`"));
+    }
+
+    #[test]
+    fn durations() {
+        let errors = errors(json!({"timeout": "0s", "url": "abc"}), |p| {
+            assert_eq!(p.duration("timeout"), None);
+            assert_eq!(p.duration("url"), None);
+            assert_eq!(p.duration("db"), None);
+        });
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0],
+            "invalid value \"0s\" for `timeout`: must not be 0"
+        );
+        assert!(errors[1].starts_with("invalid duration \"abc\" for `url`"));
+        assert!(errors(json!({"timeout": "1m 30s"}), |p| {
+            assert_eq!(p.duration("timeout"), Some(Duration::from_secs(90)));
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn sections_errors() {
+        let errors = errors(
+            json!({"db": [{"max_retries": 3}, {"max_retry": 3, "deadline": "1s"}]}),
+            |p| {
+                let sections =
+                    p.sections("db", RETRY_PROPERTIES, |p| p.optional::<u32>("max_retries"));
+                assert_eq!(sections, vec![3]);
+            },
+        );
+        assert_eq!(
+            errors,
+            vec!["in `db[1]`: unknown property `max_retry` (did you mean `max_retries`?)"]
+        );
+    }
+
+    #[test]
+    fn http_settings() {
+        let volume_cfg = config(json!({
+            "url": "http://localhost:8086",
+            "request_timeout": "0s",
+            "connect_timeout": "0ms",
+            "max_connections_per_host": 0,
+        }));
+        let error = VolumeSettings::parse(&volume_cfg).unwrap_err().to_string();
+        for expected in [
+            "invalid value \"0s\" for `request_timeout`: must not be 0",
+            "invalid value \"0ms\" for `connect_timeout`: must not be 0",
+            "`max_connections_per_host` must not be 0",
+        ] {
+            assert!(error.contains(expected), "{expected:?} not in {error:?}");
+        }
+        let volume_cfg = config(json!({
+            "url": "http://localhost:8086",
+            "request_timeout": "2s",
+            "max_connections_per_host": 4,
+        }));
+        let http = VolumeSettings::parse(&volume_cfg).unwrap().http;
+        assert_eq!(http.request_timeout, Duration::from_secs(2));
+        assert_eq!(http.connect_timeout, DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(http.max_connections_per_host, 4);
+    }
+
+    #[test]
+    fn orphan_dbs() {
+        let volume_cfg = config(json!({
+            "url": "http://localhost:8086",
+            "orphan_dbs": "drop",
+        }));
+        let error = VolumeSettings::parse(&volume_cfg).unwrap_err().to_string();
+        assert!(error.contains("`orphan_dbs` \"drop\" requires `owner` or `orphan_dbs_allow`"));
+        let volume_cfg = config(json!({
+            "url": "http://localhost:8086",
+            "orphan_dbs": "list",
+            "orphan_dbs_allow": ["zenoh_db_1"],
+            "owner": "",
+        }));
+        let error = VolumeSettings::parse(&volume_cfg).unwrap_err().to_string();
+        assert!(error.contains("`orphan_dbs_allow` requires `orphan_dbs` to be \"drop\""));
+        assert!(error.contains("`owner` must not be empty"));
+        let volume_cfg = config(json!({
+            "url": "http://localhost:8086",
+            "orphan_dbs": "drop",
+            "owner": "router1",
+        }));
+        let settings = VolumeSettings::parse(&volume_cfg).unwrap();
+        assert_eq!(settings.owner.as_deref(), Some("router1"));
+        assert!(settings.orphan_dbs_allow.is_empty());
+    }
+
+    #[test]
+    fn finish_aggregates_errors() {
+        let volume_cfg = config(json!({
+            "urls": "http://localhost:8086",
+            "health_check_interval": "0s",
+            "retry": {"max_retries": "3"},
+            "private": {"username": "u"},
+        }));
+        let error = VolumeSettings::parse(&volume_cfg).unwrap_err().to_string();
+        assert!(error.starts_with("Invalid configuration for InfluxDb volume:\n - "));
+        for expected in [
+            "\n - missing mandatory property `url`",
+            "\n - invalid value \"0s\" for `health_check_interval`: must not be 0",
+            "\n - in `retry`: invalid value \"3\" for `max_retries`",
+            "\n - optional properties `username` and `password` must coexist",
+            "\n - unknown property `urls` (did you mean `url`?)",
+        ] {
+            assert!(error.contains(expected), "{expected:?} not in {error:?}");
+        }
+        assert!(VolumeSettings::parse(&config(json!({"url": "http://localhost:8086"}))).is_ok());
+    }
+}
@@ -0,0 +1,202 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Setup of a storage in InfluxDB (its database and grants, and the databases of its mirrors and shards),
+//! deferred while InfluxDB is not reachable.
+
+use crate::endpoints::ClientPool;
+use crate::health::Health;
+use crate::settings::{Privilege, StorageSettings};
+use crate::sweep::OWNER_MEASUREMENT;
+use crate::{check_grants, create_db, is_db_existing};
+use async_std::task;
+use influxdb::{Client, Timestamp as InfluxTimestamp, WriteQuery as InfluxWQuery};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use zenoh::Result as ZResult;
+use zenoh_core::bail;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SetupState {
+    // waiting for InfluxDB to be reachable
+    Pending,
+    Ready,
+    // failed with this error
+    Failed(String),
+}
+
+/// A database to be created on another InfluxDB instance (of a mirror or of a shard).
+pub(crate) struct RemoteDb {
+    // "Mirror <url>" or "Shard <url>", for the errors
+    pub(crate) name: String,
+    pub(crate) db: String,
+    pub(crate) client: Client,
+}
+
+/// The setup of a storage, run once InfluxDB is reachable. The storage rejects all operations until it's done.
+pub(crate) struct StorageSetup {
+    storage: String,
+    db: String,
+    create_db: bool,
+    username: Option<String>,
+    privilege: Privilege,
+    // the owner marking the database, if generated (see the sweep)
+    owner: Option<String>,
+    // the clients with the volume's credentials, on no database and on the storage's database
+    admin_clients: ClientPool,
+    admin_client: ClientPool,
+    remotes: Vec<RemoteDb>,
+    state: Mutex<SetupState>,
+}
+
+impl StorageSetup {
+    pub(crate) fn new(
+        storage: &str,
+        settings: &StorageSettings,
+        owner: Option<String>,
+        admin_clients: ClientPool,
+        admin_client: ClientPool,
+        remotes: Vec<RemoteDb>,
+    ) -> Self {
+        StorageSetup {
+            storage: storage.into(),
+            db: settings.db.clone(),
+            create_db: settings.create_db,
+            username: settings.username.clone(),
+            privilege: settings.privilege,
+            owner,
+            admin_clients,
+            admin_client,
+            remotes,
+            state: Mutex::new(SetupState::Pending),
+        }
+    }
+
+    /// Fails if the storage is not set up (yet).
+    pub(crate) fn check_ready(&self) -> ZResult<()> {
+        match self.state.lock().map(|state| state.clone()) {
+            Ok(SetupState::Ready) => Ok(()),
+            Ok(SetupState::Pending) => bail!(
+                "Storage `{}` is not set up yet: InfluxDB is not reachable",
+                self.storage
+            ),
+            Ok(SetupState::Failed(e)) => {
+                bail!("Storage `{}` failed to be set up : {}", self.storage, e)
+            }
+            Err(_) => bail!("Storage `{}` failed to be set up", self.storage),
+        }
+    }
+
+    fn set_state(&self, state: SetupState) {
+        if let Ok(mut s) = self.state.lock() {
+            *s = state;
+        }
+    }
+
+    /// Set up the storage in InfluxDB, failing if it can't be.
+    pub(crate) async fn run(&self) -> ZResult<()> {
+        let db = &self.db;
+        // Check if the database exists (using backend's credentials, not depending on what the storage's user can list)
+        if !is_db_existing(self.admin_clients.active(), db).await? {
+            if self.create_db {
+                // create db using backend's credentials
+                create_db(
+                    self.admin_clients.active(),
+                    db,
+                    self.username.as_deref(),
+                    self.privilege,
+                )
+                .await?;
+            } else {
+                bail!("Database '{}' doesn't exist in InfluxDb", db)
+            }
+        }
+
+        // For read-only or write-only storages, check the user really has the privilege on the database,
+        // rather than failing on first query
+        if let (Some(username), Privilege::Read | Privilege::Write) =
+            (&self.username, self.privilege)
+        {
+            check_grants(self.admin_clients.active(), db, username, self.privilege).await?;
+        }
+
+        // Mark the random database with the volume's owner, for the sweep to drop it once orphan
+        if let Some(owner) = &self.owner {
+            let query = InfluxWQuery::new(InfluxTimestamp::Nanoseconds(0), OWNER_MEASUREMENT)
+                .add_field("owner", owner.clone());
+            if let Err(e) = self.admin_client.active().query(&query).await {
+                warn!(
+                    "Failed to mark database '{}' with owner '{}' (it won't be dropped by the sweep) : {}",
+                    db, owner, e
+                );
+            }
+        }
+
+        // Failing to create a mirror's or a shard's database fails the setup
+        if self.create_db {
+            for remote in &self.remotes {
+                if let Err(e) = create_db(&remote.client, &remote.db, None, self.privilege).await {
+                    bail!("{} of storage `{}`: {}", remote.name, self.storage, e)
+                }
+            }
+        }
+        self.set_state(SetupState::Ready);
+        Ok(())
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self.state.lock() {
+            Ok(state) => serde_json::to_value(&*state).unwrap_or_default(),
+            Err(_) => serde_json::Value::Null,
+        }
+    }
+}
+
+/// Run the setup of a storage once InfluxDB is reachable, until it's dropped.
+/// If it fails because InfluxDB is not reachable anymore, it's run again once it is.
+pub(crate) fn defer(setup: &Arc<StorageSetup>, health: &Arc<Health>) {
+    let weak_setup = Arc::downgrade(setup);
+    let weak_health = Arc::downgrade(health);
+    health.when_connected(move || {
+        task::spawn(async move {
+            let setup = match weak_setup.upgrade() {
+                Some(setup) => setup,
+                // the storage has been dropped
+                None => return,
+            };
+            let e = match setup.run().await {
+                Ok(()) => {
+                    info!("Storage `{}` is now set up in InfluxDB", setup.storage);
+                    return;
+                }
+                Err(e) => e,
+            };
+            match weak_health.upgrade() {
+                Some(health) if !health.is_connected() => {
+                    warn!(
+                        "Failed to set up storage `{}` (retried once InfluxDB is reachable) : {}",
+                        setup.storage, e
+                    );
+                    defer(&setup, &health);
+                }
+                _ => {
+                    error!("Failed to set up storage `{}` : {}", setup.storage, e);
+                    setup.set_state(SetupState::Failed(e.to_string()));
+                }
+            }
+        });
+    });
+}
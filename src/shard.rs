@@ -0,0 +1,154 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Sharding of a storage's keys across several InfluxDB instances, by key expression.
+
+use influxdb::Client;
+use std::str::FromStr;
+use zenoh::prelude::OwnedKeyExpr;
+
+/// A shard of the storage, storing the keys matching its key expression on another InfluxDB instance.
+pub(crate) struct Shard {
+    pub(crate) key_expr: OwnedKeyExpr,
+    pub(crate) url: String,
+    pub(crate) client: Client,
+}
+
+/// The shards of a storage. The keys not matching any shard are stored in the storage's database.
+pub(crate) struct Shards {
+    shards: Vec<Shard>,
+    strip_prefix: Option<OwnedKeyExpr>,
+}
+
+impl Shards {
+    pub(crate) fn new(shards: Vec<Shard>, strip_prefix: Option<OwnedKeyExpr>) -> Self {
+        Shards {
+            shards,
+            strip_prefix,
+        }
+    }
+
+    pub(crate) fn all(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    /// The full key expression, as received by the storage (i.e. with the `strip_prefix`)
+    pub(crate) fn full_key_expr(&self, key: &Option<OwnedKeyExpr>) -> Option<OwnedKeyExpr> {
+        match (&self.strip_prefix, key) {
+            (Some(prefix), Some(key)) => OwnedKeyExpr::from_str(&format!("{prefix}/{key}")).ok(),
+            (Some(prefix), None) => Some(prefix.clone()),
+            (None, key) => key.clone(),
+        }
+    }
+
+    /// The shard storing a key (the 1st one matching it), or None if stored in the storage's database.
+    pub(crate) fn route(&self, key: &Option<OwnedKeyExpr>) -> Option<&Shard> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let key = self.full_key_expr(key)?;
+        self.shards.iter().find(|s| s.key_expr.includes(&key))
+    }
+
+    /// The shards possibly storing keys matching a key expression. None stands for the storage's database,
+    /// which is not queried if a shard includes the whole key expression.
+    pub(crate) fn fan_out(&self, key: &Option<OwnedKeyExpr>) -> Vec<Option<&Shard>> {
+        let key = match self.full_key_expr(key) {
+            Some(key) if !self.shards.is_empty() => key,
+            _ => return vec![None],
+        };
+        let mut targets: Vec<Option<&Shard>> = self
+            .shards
+            .iter()
+            .filter(|s| s.key_expr.intersects(&key))
+            .map(Some)
+            .collect();
+        if !self.shards.iter().any(|s| s.key_expr.includes(&key)) {
+            targets.insert(0, None);
+        }
+        targets
+    }
+
+    /// The storage's database and all the shards.
+    pub(crate) fn everywhere(&self) -> Vec<Option<&Shard>> {
+        std::iter::once(None)
+            .chain(self.shards.iter().map(Some))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> Option<OwnedKeyExpr> {
+        Some(OwnedKeyExpr::from_str(s).unwrap())
+    }
+
+    fn shards(key_exprs: &[&str], strip_prefix: Option<OwnedKeyExpr>) -> Shards {
+        let shards = key_exprs
+            .iter()
+            .map(|k| Shard {
+                key_expr: OwnedKeyExpr::from_str(k).unwrap(),
+                url: format!("http://{k}"),
+                client: Client::new(format!("http://{k}"), "db"),
+            })
+            .collect();
+        Shards::new(shards, strip_prefix)
+    }
+
+    fn urls(targets: Vec<Option<&Shard>>) -> Vec<Option<&str>> {
+        targets
+            .into_iter()
+            .map(|s| s.map(|s| s.url.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn route() {
+        let shards = shards(&["site/1/**", "site/*/temp"], key("site"));
+        // the 1st matching shard
+        let shard = shards.route(&key("1/temp")).unwrap();
+        assert_eq!(shard.url, "http://site/1/**");
+        let shard = shards.route(&key("2/temp")).unwrap();
+        assert_eq!(shard.url, "http://site/*/temp");
+        assert!(shards.route(&key("2/humidity")).is_none());
+        // the key equal to the `strip_prefix`
+        assert!(shards.route(&None).is_none());
+        assert!(self::shards(&[], None).route(&key("a")).is_none());
+    }
+
+    #[test]
+    fn fan_out() {
+        let shards = shards(&["site/1/**", "site/*/temp"], key("site"));
+        assert_eq!(
+            urls(shards.fan_out(&key("**"))),
+            [None, Some("http://site/1/**"), Some("http://site/*/temp")]
+        );
+        // a shard includes the whole key expression: the storage's database is not queried
+        assert_eq!(
+            urls(shards.fan_out(&key("1/a/**"))),
+            [Some("http://site/1/**")]
+        );
+        assert_eq!(
+            urls(shards.fan_out(&key("*/temp"))),
+            [Some("http://site/1/**"), Some("http://site/*/temp")]
+        );
+        assert_eq!(urls(shards.fan_out(&key("2/humidity"))), [None]);
+        assert_eq!(
+            urls(shards.everywhere()),
+            [None, Some("http://site/1/**"), Some("http://site/*/temp")]
+        );
+    }
+}
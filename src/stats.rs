@@ -0,0 +1,212 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Runtime statistics of a storage, exposed in its admin status.
+
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use zenoh::Result as ZResult;
+use zenoh_backend_traits::StorageInsertionResult;
+
+// Upper bounds (in milliseconds) of the latency histograms buckets
+pub(crate) const LATENCY_BUCKETS_MS: [u64; 12] =
+    [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// The last error of a storage, of the connection or of a mirror, exposed in their admin status.
+#[derive(Default)]
+pub(crate) struct LastError(Mutex<Option<(String, SystemTime)>>);
+
+impl LastError {
+    pub(crate) fn set<E: std::fmt::Display>(&self, e: &E) {
+        if let Ok(mut last_error) = self.0.lock() {
+            *last_error = Some((e.to_string(), SystemTime::now()));
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self.0.lock() {
+            Ok(guard) => match &*guard {
+                Some((msg, time)) => json!({
+                    "message": msg,
+                    "time": humantime::format_rfc3339(*time).to_string(),
+                }),
+                None => serde_json::Value::Null,
+            },
+            Err(_) => serde_json::Value::Null,
+        }
+    }
+}
+
+/// A histogram of latencies, with fixed buckets.
+#[derive(Default)]
+pub(crate) struct LatencyHistogram {
+    // buckets[i] counts the latencies <= LATENCY_BUCKETS_MS[i] (and > the previous bound);
+    // the last one counts the latencies above all bounds
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&self, latency: Duration) {
+        let ms = latency.as_millis() as u64;
+        let i = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_us.load(Ordering::Relaxed))
+    }
+
+    // The counts of each bucket (not cumulative), the last one being for +Inf
+    pub(crate) fn buckets(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut buckets = serde_json::Map::new();
+        for (i, count) in self.buckets().into_iter().enumerate() {
+            let name = match LATENCY_BUCKETS_MS.get(i) {
+                Some(bound) => format!("le_{bound}ms"),
+                None => "inf".into(),
+            };
+            buckets.insert(name, count.into());
+        }
+        json!({
+            "count": self.count(),
+            "sum_ms": self.sum().as_secs_f64() * 1000.0,
+            "buckets": buckets,
+        })
+    }
+}
+
+/// The outcome of a PUT or a DELETE, more detailed than its StorageInsertionResult for the statistics.
+/// All but `Stored` are reported as `Outdated` to the storage manager, the sample not being the key's value.
+pub(crate) enum WriteOutcome {
+    Stored(StorageInsertionResult),
+    // older than a deletion or than the stored value, or colliding with a kept sample
+    Outdated,
+    // skipped by a filter (rate limit, deadband, unchanged value, quotas or clock skew guard)
+    Filtered,
+    // written apart, in a quarantine measurement
+    Quarantined,
+}
+
+/// Counters and gauges of a storage.
+#[derive(Default)]
+pub(crate) struct StorageStats {
+    pub(crate) puts: AtomicU64,
+    pub(crate) deletes: AtomicU64,
+    pub(crate) gets: AtomicU64,
+    pub(crate) outdated: AtomicU64,
+    // samples skipped by a filter
+    pub(crate) filtered: AtomicU64,
+    // samples with a timestamp too far in the future, written in a quarantine measurement
+    pub(crate) quarantined: AtomicU64,
+    // samples with the same time than a stored point of their key
+    pub(crate) collisions: AtomicU64,
+    // samples dropped by the incoming interceptor
+    pub(crate) dropped: AtomicU64,
+    pub(crate) errors: AtomicU64,
+    pub(crate) retries: AtomicU64,
+    pub(crate) bytes_written: AtomicU64,
+    pub(crate) base64_payloads: AtomicU64,
+    pub(crate) pending_drops: AtomicU64,
+    pub(crate) write_latency: LatencyHistogram,
+    pub(crate) query_latency: LatencyHistogram,
+    last_error: LastError,
+}
+
+impl StorageStats {
+    pub(crate) fn incr(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn decr(counter: &AtomicU64) {
+        // never go below 0, even if a timer ran after the stats were reset
+        let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_sub(1));
+    }
+
+    pub(crate) fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    // Count the outcome of a PUT or a DELETE (in `stored` if stored), returning the storage manager's result
+    pub(crate) fn record_outcome(
+        &self,
+        stored: &AtomicU64,
+        outcome: ZResult<WriteOutcome>,
+    ) -> ZResult<StorageInsertionResult> {
+        let (counter, result) = match outcome {
+            Ok(WriteOutcome::Stored(result)) => (stored, result),
+            Ok(WriteOutcome::Outdated) => (&self.outdated, StorageInsertionResult::Outdated),
+            Ok(WriteOutcome::Filtered) => (&self.filtered, StorageInsertionResult::Outdated),
+            Ok(WriteOutcome::Quarantined) => (&self.quarantined, StorageInsertionResult::Outdated),
+            Err(e) => {
+                self.record_error(&e);
+                return Err(e);
+            }
+        };
+        Self::incr(counter);
+        Ok(result)
+    }
+
+    pub(crate) fn record_error<E: std::fmt::Display>(&self, e: &E) {
+        Self::incr(&self.errors);
+        self.last_error.set(e);
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let puts = Self::get(&self.puts);
+        let base64_payloads = Self::get(&self.base64_payloads);
+        json!({
+            "puts": puts,
+            "deletes": Self::get(&self.deletes),
+            "gets": Self::get(&self.gets),
+            "outdated": Self::get(&self.outdated),
+            "filtered": Self::get(&self.filtered),
+            "quarantined": Self::get(&self.quarantined),
+            "collisions": Self::get(&self.collisions),
+            "dropped": Self::get(&self.dropped),
+            "errors": Self::get(&self.errors),
+            "retries": Self::get(&self.retries),
+            "bytes_written": Self::get(&self.bytes_written),
+            "base64_payloads": base64_payloads,
+            "base64_ratio": if puts == 0 { 0.0 } else { base64_payloads as f64 / puts as f64 },
+            "pending_measurement_drops": Self::get(&self.pending_drops),
+            "write_latency": self.write_latency.to_json(),
+            "query_latency": self.query_latency.to_json(),
+            "last_error": self.last_error.to_json(),
+        })
+    }
+}
@@ -0,0 +1,254 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+
+//! Sweep of the orphan databases, generated for storages which don't exist anymore.
+
+use crate::endpoints::ClientPool;
+use crate::health::Health;
+use crate::{influxql, show_databases, GENERATED_DB_PREFIX};
+use async_std::task;
+use influxdb::{Client, ReadQuery as InfluxRQuery};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
+use zenoh::Result as ZResult;
+use zenoh_core::bail;
+
+// polling period of the health state while waiting for InfluxDB to be reachable
+const WAIT_CONNECTED_PERIOD: Duration = Duration::from_millis(500);
+
+// The measurement marking a generated database with the owner of the volume which created it.
+// As '#' is forbidden in key expressions, it never matches a key.
+pub(crate) const OWNER_MEASUREMENT: &str = "#owner";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OrphanDbs {
+    // no sweep
+    #[default]
+    Off,
+    // the orphan databases are listed in the logs and in the admin status
+    List,
+    // the orphan databases marked with the volume's owner (or allowed) are dropped, the others are listed
+    Drop,
+}
+
+/// The databases used by the storages of a volume, and the result of the sweep.
+pub(crate) struct Sweep {
+    mode: OrphanDbs,
+    // the owner marked in the generated databases, if any
+    owner: Option<String>,
+    // the orphan databases dropped although not marked with the owner
+    allow: HashSet<String>,
+    // the databases used by the storages, with their count of storages
+    used: Mutex<HashMap<String, usize>>,
+    orphans: Mutex<Vec<String>>,
+    dropped: Mutex<Vec<String>>,
+    last_sweep: Mutex<Option<SystemTime>>,
+}
+
+/// The registration of a database used by a storage, undone when dropped with the storage.
+pub(crate) struct Registration {
+    sweep: Weak<Sweep>,
+    db: String,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(sweep) = self.sweep.upgrade() {
+            if let Ok(mut used) = sweep.used.lock() {
+                if let Some(count) = used.get_mut(&self.db) {
+                    *count -= 1;
+                    if *count == 0 {
+                        used.remove(&self.db);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Sweep {
+    /// Register a database used by a storage, not to be swept while the returned Registration lives.
+    pub(crate) fn register(self: &Arc<Self>, db: &str) -> Registration {
+        if let Ok(mut used) = self.used.lock() {
+            *used.entry(db.into()).or_default() += 1;
+        }
+        Registration {
+            sweep: Arc::downgrade(self),
+            db: db.into(),
+        }
+    }
+
+    // The generated databases not used by any storage
+    fn find_orphans(&self, dbs: Vec<String>) -> Vec<String> {
+        let used = match self.used.lock() {
+            Ok(used) => used,
+            Err(_) => return Vec::new(),
+        };
+        dbs.into_iter()
+            .filter(|db| db.starts_with(GENERATED_DB_PREFIX) && !used.contains_key(db))
+            .collect()
+    }
+
+    // If an orphan database can be dropped: explicitly allowed, or marked with the volume's owner
+    async fn is_droppable(&self, client: &Client, db: &str) -> bool {
+        if self.allow.contains(db) {
+            return true;
+        }
+        match &self.owner {
+            Some(owner) => match is_owned_by(client, db, owner).await {
+                Ok(owned) => owned,
+                Err(e) => {
+                    warn!(
+                        "Failed to read the owner of orphan database '{}' : {}",
+                        db, e
+                    );
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+
+    async fn run(&self, clients: &ClientPool) {
+        let client = clients.active();
+        let dbs = match show_databases(client).await {
+            Ok(dbs) => dbs,
+            Err(e) => {
+                warn!("Failed to sweep the orphan databases : {}", e);
+                return;
+            }
+        };
+        let orphans = self.find_orphans(dbs);
+        if let Ok(mut last_sweep) = self.last_sweep.lock() {
+            *last_sweep = Some(SystemTime::now());
+        }
+        if orphans.is_empty() {
+            info!("No orphan database found");
+        } else if self.mode == OrphanDbs::List {
+            warn!("Found orphan databases: {:?}", orphans);
+        } else {
+            let mut kept = Vec::new();
+            for db in &orphans {
+                if !self.is_droppable(client, db).await {
+                    kept.push(db);
+                    continue;
+                }
+                let query = InfluxRQuery::new(influxql::drop_database(db));
+                match client.query(&query).await {
+                    Ok(_) => {
+                        info!("Dropped orphan database {}", db);
+                        if let Ok(mut dropped) = self.dropped.lock() {
+                            dropped.push(db.clone());
+                        }
+                    }
+                    Err(e) => warn!("Failed to drop orphan database '{}' : {}", db, e),
+                }
+            }
+            if !kept.is_empty() {
+                warn!(
+                    "Found orphan databases, not dropped as not created by this volume: {:?}",
+                    kept
+                );
+            }
+        }
+        if let Ok(mut found) = self.orphans.lock() {
+            *found = orphans;
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let last_sweep = self
+            .last_sweep
+            .lock()
+            .ok()
+            .and_then(|t| *t)
+            .map(|t| humantime::format_rfc3339(t).to_string());
+        json!({
+            "mode": self.mode,
+            "last_sweep": last_sweep,
+            "orphans": self.orphans.lock().map(|o| o.clone()).unwrap_or_default(),
+            "dropped": self.dropped.lock().map(|d| d.clone()).unwrap_or_default(),
+        })
+    }
+}
+
+// If a database is marked with an owner (see OWNER_MEASUREMENT)
+async fn is_owned_by(client: &Client, db: &str, owner: &str) -> ZResult<bool> {
+    #[derive(Deserialize)]
+    struct Owner {
+        owner: String,
+    }
+    let query = InfluxRQuery::new(influxql::select_owners(db, OWNER_MEASUREMENT));
+    debug!(
+        "Get the owner of database {} with Influx query: {:?}",
+        db, query
+    );
+    match client.json_query(query).await {
+        Ok(mut result) => match result.deserialize_next::<Owner>() {
+            Ok(owners) => Ok(owners
+                .series
+                .into_iter()
+                .flat_map(|serie| serie.values)
+                .any(|o| o.owner == owner)),
+            Err(e) => bail!("Failed to parse the owner of database '{}' : {}", db, e),
+        },
+        Err(e) => bail!("Failed to get the owner of database '{}' : {}", db, e),
+    }
+}
+
+/// Sweep the orphan databases once, after a delay from the volume's start (leaving time to the storages
+/// to be created) and once InfluxDB is reachable. The sweep is cancelled if the returned Sweep is dropped.
+pub(crate) fn start_sweep(
+    mode: OrphanDbs,
+    owner: Option<String>,
+    allow: Vec<String>,
+    clients: ClientPool,
+    health: &Arc<Health>,
+    delay: Duration,
+) -> Arc<Sweep> {
+    let sweep = Arc::new(Sweep {
+        mode,
+        owner,
+        allow: allow.into_iter().collect(),
+        used: Mutex::new(HashMap::new()),
+        orphans: Mutex::new(Vec::new()),
+        dropped: Mutex::new(Vec::new()),
+        last_sweep: Mutex::new(None),
+    });
+    if mode == OrphanDbs::Off {
+        return sweep;
+    }
+    let weak: Weak<Sweep> = Arc::downgrade(&sweep);
+    let health: Weak<Health> = Arc::downgrade(health);
+    task::spawn(async move {
+        task::sleep(delay).await;
+        loop {
+            match health.upgrade() {
+                Some(health) if health.is_connected() => break,
+                Some(_) => task::sleep(WAIT_CONNECTED_PERIOD).await,
+                // the volume has been dropped
+                None => return,
+            }
+        }
+        if let Some(sweep) = weak.upgrade() {
+            sweep.run(&clients).await;
+        }
+    });
+    sweep
+}